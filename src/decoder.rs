@@ -2,7 +2,7 @@
 // use std::io;
 // use std::marker::PhantomData;
 // use std::result::Result;
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, SeekFrom};
 // use std::error::Error;
 // use byteorder;
 // use std::path::Path;
@@ -38,9 +38,6 @@ pub trait ImageDecoder: Sized {
     /// Decodes the entire image and return it as a Vector
     fn read_image(&mut self) -> ImageResult<DecodingResult>;
 
-    // Decodes the entire image and return it as a Vector
-    // fn write_image(self, path:Path, ) -> ImageResult<DecodingResult>;
-
 }
 
 
@@ -68,16 +65,27 @@ impl<R: Read + Seek> IDPDecoder<R> {
         }.init()
     }
 
-    fn read_header(&mut self) -> ImageResult<()> {
+    fn read_frame_header(&mut self) -> ImageResult<( PixelType, u32, u32 )> {
         let fmt1 = try!(self.reader.read_u32() );
         let fmt2 = try!(self.reader.read_u32() );
-        self.pixel_type = match ( fmt1, fmt2 ) {
+        let pixel_type = match ( fmt1, fmt2 ) {
             ( 0, 0 ) => PixelType::Short16,
             ( 0, 2 ) => PixelType::Float32,
-            _ => panic!("Invalid IDP header found" )
+            _ => return Err( ImageError::BadHeader(
+                    format!( "unknown pixel format ( {}, {} )", fmt1, fmt2 )
+                ) )
         };
-        self.width  = try!(self.reader.read_u32() );
-        self.height = try!(self.reader.read_u32() );
+        let width  = try!(self.reader.read_u32() );
+        let height = try!(self.reader.read_u32() );
+
+        Ok(( pixel_type, width, height ))
+    }
+
+    fn read_header(&mut self) -> ImageResult<()> {
+        let ( pixel_type, width, height ) = try!(self.read_frame_header());
+        self.pixel_type = pixel_type;
+        self.width  = width;
+        self.height = height;
         
         Ok(())
     }
@@ -94,6 +102,42 @@ impl<R: Read + Seek> IDPDecoder<R> {
         Ok(self)
     }
 
+    /// Returns true if another image follows the one that was just read with `read_image`.
+    /// A file with several frames is a concatenation of IDP images, each with its own header.
+    pub fn more_images(&mut self) -> ImageResult<bool> {
+        Ok(try!(self.bytes_left()) > 0)
+    }
+
+    /// Counts the images in the stream without decoding them.
+    /// The position in the stream is left unchanged.
+    pub fn frame_count(&mut self) -> ImageResult<usize> {
+        let here = try!(self.reader.seek(SeekFrom::Current(0)));
+        let end  = try!(self.reader.seek(SeekFrom::End(0)));
+        let mut pos = try!(self.reader.seek(SeekFrom::Start(0)));
+        let mut count = 0usize;
+        while pos < end {
+            let ( pixel_type, width, height ) = try!(self.read_frame_header());
+            pos += 16 + width as u64 * height as u64 * pixel_type.bytes_per_pixel();
+            if pos > end {
+                return Err( ImageError::TruncatedData(
+                    format!( "frame {} ends {} bytes after the end of the file", count, pos - end )
+                ) );
+            }
+            try!(self.reader.seek(SeekFrom::Start(pos)));
+            count += 1;
+        }
+        try!(self.reader.seek(SeekFrom::Start(here)));
+        Ok(count)
+    }
+
+    /// Number of bytes left in the stream after the current position.
+    fn bytes_left(&mut self) -> ImageResult<u64> {
+        let here = try!(self.reader.seek(SeekFrom::Current(0)));
+        let end  = try!(self.reader.seek(SeekFrom::End(0)));
+        try!(self.reader.seek(SeekFrom::Start(here)));
+        Ok(end - here)
+    }
+
     /// Decompresses the strip into the supplied buffer.
     /// Returns the number of bytes read.
     fn expand_strip<'a>(&mut self, decode_buffer: DecodingBuffer<'a> ) -> ImageResult<()> {
@@ -141,6 +185,15 @@ impl<R: Read + Seek> ImageDecoder for IDPDecoder<R> {
         let number_of_pixels =
               self.width  as usize
             * self.height as usize;
+        let number_of_bytes = number_of_pixels as u64 * self.pixel_type.bytes_per_pixel();
+        // check before allocating, a corrupt header can announce a huge image
+        let bytes_left = try!(self.bytes_left());
+        if bytes_left < number_of_bytes {
+            return Err( ImageError::TruncatedData(
+                format!( "a {}x{} {:?} image needs {} bytes but only {} are left",
+                         self.width, self.height, self.pixel_type, number_of_bytes, bytes_left )
+            ) );
+        }
         let mut result = match self.pixel_type { 
             PixelType::Short16 => DecodingResult::U16( Vec::with_capacity(number_of_pixels)), 
             PixelType::Float32 => DecodingResult::F32( Vec::with_capacity(number_of_pixels)), 
@@ -0,0 +1,167 @@
+use std::io::{Write, Seek};
+
+use image::error::{
+    ImageError,
+    ImageResult
+};
+
+use image::other::{
+    PixelType,
+    EncodingBuffer
+};
+
+use super::stream::{
+    ByteOrder,
+    EndianWriter,
+    SmartWriter
+};
+
+
+
+/// Writes images in the IDP format read by `IDPDecoder`
+#[derive(Debug)]
+pub struct IDPEncoder<W> where W: Write + Seek {
+    writer: SmartWriter<W>,
+}
+
+
+impl<W: Write + Seek> IDPEncoder<W> {
+    /// Create a new encoder that writes to the stream ```w```
+    pub fn new(w: W) -> IDPEncoder<W> {
+        IDPEncoder {
+            writer: SmartWriter::wrap(w, ByteOrder::LittleEndian),
+        }
+    }
+
+    fn write_header(&mut self, width: u32, height: u32, pixel_type: PixelType) -> ImageResult<()> {
+        let ( fmt1, fmt2 ) = match pixel_type {
+            PixelType::Short16 => ( 0, 0 ),
+            PixelType::Float32 => ( 0, 2 ),
+        };
+        try!(self.writer.write_u32( fmt1 ) );
+        try!(self.writer.write_u32( fmt2 ) );
+        try!(self.writer.write_u32( width  ) );
+        try!(self.writer.write_u32( height ) );
+
+        Ok(())
+    }
+
+    /// Writes one image, the header followed by the pixels row by row.
+    /// The pixel type of the file is taken from the buffer.
+    pub fn write_image(&mut self, width: u32, height: u32, buffer: EncodingBuffer) -> ImageResult<()> {
+        let number_of_pixels =
+              width  as usize
+            * height as usize;
+        if buffer.len() != number_of_pixels {
+            return Err( ImageError::DimensionMismatch(
+                format!( "a {}x{} image needs {} pixels but the buffer has {}", width, height, number_of_pixels, buffer.len() )
+            ) );
+        }
+
+        match buffer {
+            EncodingBuffer::U16(buffer) => {
+                try!(self.write_header(width, height, PixelType::Short16));
+                for datum in buffer.iter() {
+                    try!(self.writer.write_u16(*datum));
+                }
+            },
+            EncodingBuffer::F32(buffer) => {
+                try!(self.write_header(width, height, PixelType::Float32));
+                for datum in buffer.iter() {
+                    try!(self.writer.write_f32(*datum));
+                }
+            },
+        }
+        try!(self.writer.flush());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use decoder::{
+        IDPDecoder,
+        ImageDecoder
+    };
+    use image::error::ImageError;
+    use image::other::{
+        DecodingResult,
+        EncodingBuffer,
+        PixelType
+    };
+
+    use super::IDPEncoder;
+
+    fn encode( width: u32, height: u32, buffer: EncodingBuffer ) -> Vec<u8> {
+        let mut bytes = Cursor::new( Vec::new() );
+        IDPEncoder::new( &mut bytes ).write_image( width, height, buffer ).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn short16_round_trip() {
+        let values = ( 0..12 ).map( | v | v * 5000 ).collect::<Vec<u16>>();
+        let bytes = encode( 4, 3, EncodingBuffer::U16( &values ) );
+        assert_eq!( bytes.len(), 16 + 2 * 12 );
+        let mut decoder = IDPDecoder::new( Cursor::new( bytes ) ).unwrap();
+        assert_eq!( decoder.dimensions().unwrap(), ( 4, 3 ) );
+        assert_eq!( decoder.pixel_type().unwrap(), PixelType::Short16 );
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16( decoded ) => assert_eq!( decoded, values ),
+            DecodingResult::F32( _ ) => panic!( "a Short16 image decoded as Float32" ),
+        }
+        assert!( !decoder.more_images().unwrap() );
+    }
+
+    #[test]
+    fn float32_round_trip() {
+        let values = vec![ 0.0, -1.5, 3.25e7, ::std::f32::MIN_POSITIVE, 1.0 / 3.0, 42.0 ];
+        let bytes = encode( 3, 2, EncodingBuffer::F32( &values ) );
+        assert_eq!( bytes.len(), 16 + 4 * 6 );
+        let mut decoder = IDPDecoder::new( Cursor::new( bytes ) ).unwrap();
+        assert_eq!( decoder.dimensions().unwrap(), ( 3, 2 ) );
+        assert_eq!( decoder.pixel_type().unwrap(), PixelType::Float32 );
+        match decoder.read_image().unwrap() {
+            DecodingResult::F32( decoded ) => assert_eq!( decoded, values ),
+            DecodingResult::U16( _ ) => panic!( "a Float32 image decoded as Short16" ),
+        }
+    }
+
+    #[test]
+    fn buffer_of_the_wrong_size() {
+        let mut bytes = Cursor::new( Vec::new() );
+        match IDPEncoder::new( &mut bytes ).write_image( 2, 2, EncodingBuffer::U16( &[ 1, 2, 3 ] ) ) {
+            Err( ImageError::DimensionMismatch( _ ) ) => {},
+            other => panic!( "expected a dimension mismatch, got {:?}", other ),
+        }
+    }
+
+    #[test]
+    fn truncated_data() {
+        let mut bytes = encode( 4, 3, EncodingBuffer::F32( &[ 1.0; 12 ] ) );
+        let len = bytes.len();
+        bytes.truncate( len - 1 );
+        let mut decoder = IDPDecoder::new( Cursor::new( bytes ) ).unwrap();
+        match decoder.read_image() {
+            Err( ImageError::TruncatedData( _ ) ) => {},
+            other => panic!( "expected truncated data, got {:?}", other.map( | _ | () ) ),
+        }
+        // a header cut short is an error too, not an image
+        let bytes = encode( 1, 1, EncodingBuffer::U16( &[ 7 ] ) );
+        assert!( IDPDecoder::new( Cursor::new( bytes[ ..10 ].to_vec() ) ).is_err() );
+    }
+
+    #[test]
+    fn bad_header() {
+        let mut bytes = encode( 1, 1, EncodingBuffer::U16( &[ 7 ] ) );
+        // the second word of the pixel format is 0 for Short16 and 2 for Float32
+        bytes[4] = 1;
+        match IDPDecoder::new( Cursor::new( bytes ) ) {
+            Err( ImageError::BadHeader( _ ) ) => {},
+            other => panic!( "expected a bad header, got {:?}", other.map( | _ | () ) ),
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use std::io;
 use std::fmt;
+use std::path::Path;
 use std::convert::From;
 use std::error::Error;
 use byteorder;
@@ -10,6 +11,16 @@ use byteorder;
 pub enum ImageError {
     /// The Image is not formatted properly
     FormatError(String),
+    /// The header does not describe a known IDP image
+    BadHeader(String),
+    /// The file ends before all the pixels announced in the header
+    TruncatedData(String),
+    /// The images that are compared do not have the same width and height
+    DimensionMismatch(String),
+    /// One of the two images of an open or short pair was not found
+    MissingPairMember(String),
+    /// The file name does not follow the naming of IDP captures
+    BadCaptureName(String),
         /// An I/O Error occurred while decoding the image
     IoError(io::Error)
 }
@@ -20,6 +31,11 @@ impl fmt::Display for ImageError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         match self {
             &ImageError::FormatError(ref e) => write!(fmt, "Format error: {}", e),
+            &ImageError::BadHeader(ref e) => write!(fmt, "Bad header: {}", e),
+            &ImageError::TruncatedData(ref e) => write!(fmt, "Truncated data: {}", e),
+            &ImageError::DimensionMismatch(ref e) => write!(fmt, "Dimension mismatch: {}", e),
+            &ImageError::MissingPairMember(ref e) => write!(fmt, "Missing pair member: {}", e),
+            &ImageError::BadCaptureName(ref e) => write!(fmt, "Bad capture name: {}", e),
             &ImageError::IoError(ref e) => e.fmt(fmt)
         }
     }
@@ -29,6 +45,11 @@ impl Error for ImageError {
     fn description (&self) -> &str {
         match *self {
             ImageError::FormatError(..) => &"Format error",
+            ImageError::BadHeader(..) => &"Bad header",
+            ImageError::TruncatedData(..) => &"Truncated data",
+            ImageError::DimensionMismatch(..) => &"Dimension mismatch",
+            ImageError::MissingPairMember(..) => &"Missing pair member",
+            ImageError::BadCaptureName(..) => &"Bad capture name",
             ImageError::IoError(..) => &"IO error"
         }
     }
@@ -41,6 +62,22 @@ impl Error for ImageError {
     }
 }
 
+impl ImageError {
+    /// Adds the name of the file being read to the message.
+    pub fn for_file(self, path: &Path) -> ImageError {
+        let name = path.display();
+        match self {
+            ImageError::FormatError(e) => ImageError::FormatError(format!("{}: {}", name, e)),
+            ImageError::BadHeader(e) => ImageError::BadHeader(format!("{}: {}", name, e)),
+            ImageError::TruncatedData(e) => ImageError::TruncatedData(format!("{}: {}", name, e)),
+            ImageError::DimensionMismatch(e) => ImageError::DimensionMismatch(format!("{}: {}", name, e)),
+            ImageError::MissingPairMember(e) => ImageError::MissingPairMember(format!("{}: {}", name, e)),
+            ImageError::BadCaptureName(e) => ImageError::BadCaptureName(format!("{}: {}", name, e)),
+            ImageError::IoError(e) => ImageError::IoError(io::Error::new(e.kind(), format!("{}: {}", name, e))),
+        }
+    }
+}
+
 impl From<io::Error> for ImageError {
     fn from(err: io::Error) -> ImageError {
         ImageError::IoError(err)
@@ -51,7 +88,7 @@ impl From<io::Error> for ImageError {
 impl From<byteorder::Error> for ImageError {
     fn from(err: byteorder::Error) -> ImageError {
         match err {
-            byteorder::Error::UnexpectedEOF => ImageError::FormatError( "Format error: ".to_string()  ),
+            byteorder::Error::UnexpectedEOF => ImageError::TruncatedData( "unexpected end of file".to_string()  ),
             byteorder::Error::Io(err) => ImageError::IoError(err),
         }
     }
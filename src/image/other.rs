@@ -1,9 +1,23 @@
+use utils::clusters::Cluster;
+use utils::dimensions::Dimensions;
+use utils::shortlines::ShortLines;
+
 #[derive(Copy, PartialEq, Eq, Debug, Clone)]
 pub enum PixelType {
     Short16,
     Float32
 }
 
+impl PixelType {
+    /// Number of bytes one pixel takes in an IDP file
+    pub fn bytes_per_pixel(&self) -> u64 {
+        match *self {
+            PixelType::Short16 => 2,
+            PixelType::Float32 => 4,
+        }
+    }
+}
+
 
 /// Result of a decoding process
 pub enum DecodingResult {
@@ -21,6 +35,33 @@ pub enum DecodingBuffer<'a> {
     F32(&'a mut [f32]),
 }
 
+/// A buffer for image encoding
+pub enum EncodingBuffer<'a> {
+    /// A slice of unsigned words
+    U16(&'a [u16]),
+    /// A slice of f32
+    F32(&'a [f32]),
+}
+
+impl<'a> EncodingBuffer<'a> {
+    /// Number of pixels in the buffer
+    pub fn len(&self) -> usize {
+        match *self {
+            EncodingBuffer::U16(buffer) => buffer.len(),
+            EncodingBuffer::F32(buffer) => buffer.len(),
+        }
+    }
+}
+
+impl<'a> From<&'a DecodingResult> for EncodingBuffer<'a> {
+    fn from(result: &'a DecodingResult) -> EncodingBuffer<'a> {
+        match *result {
+            DecodingResult::U16(ref buffer) => EncodingBuffer::U16(buffer),
+            DecodingResult::F32(ref buffer) => EncodingBuffer::F32(buffer),
+        }
+    }
+}
+
 #[derive(Copy, PartialEq, Eq, Debug, Clone)]
 pub enum BadType {
     DeadBand,
@@ -36,22 +77,58 @@ pub enum BadType {
     Unknown
 }
 
-pub struct Pixel {
-pub    value: f32,
-pub    valid: BadType
+/// Results of the open test, on the pair of images with v1 == v2
+#[derive(Copy, PartialEq, Debug, Clone)]
+pub struct OpenTestResult {
+    pub bad_opens : u64,
+    /// the global threshold, None when every pixel has its own
+    pub open_threshold : Option<f32>,
+    pub number_of_bad_columns: u64,
+    pub number_of_bad_rows: u64,
+    /// runs of open bads along columns that are not bad as a whole
+    pub number_of_bad_col_segments: u64,
+    /// runs of open bads along rows that are not bad as a whole
+    pub number_of_bad_row_segments: u64,
+    pub number_of_open_bads_in_bad_cols: usize,
+    pub number_of_open_bads_in_bad_rows: usize,
+    pub number_of_pixels_measured: u64,
+}
+
+/// Results of the short test, on the pair of images with v1 != v2
+#[derive(Copy, PartialEq, Debug, Clone)]
+pub struct ShortTestResult {
+    pub bad_shorts : u64,
+    pub threshold_for_shorts: f32,
+    /// The line counts are over all the orientations of the short test.
+    pub number_of_short_bads_not_in_bad_lines: usize,
+    pub number_of_bad_lines: usize,
+    /// pairs of bad lines of the same orientation next to each other
+    pub number_of_adjacent_bad_lines: usize,
 }
 
-//pub struct _BondingStats {
-//    pub bad_opens : u64, 
-//    pub number_of_bad_columns: u64,
-//    pub number_of_bad_rows: u64,
-//    pub number_of_bad_shorts: u64,
-//    pub threshold_for_shorts: u64,
-//    pub number_of_pixels_measured: u64,
-//    pub number_of_open_bads_in_bad_cols: u64,
-//    pub number_of_open_bads_in_bad_rows: u64
-//}
+/// Everything measured on the images of one die
+pub struct DieResult {
+    pub dims: Dimensions,
+    /// the fewest frames of the captures of the die, 1 for files of a single frame
+    pub frames: usize,
+    pub open: OpenTestResult,
+    pub short: ShortTestResult,
+    /// absolute difference of the open pair
+    pub open_diff: Vec<f32>,
+    /// absolute difference of the short pair, empty when the short test was not run
+    pub short_diff: Vec<f32>,
+    /// final classification of every pixel, after both tests
+    pub defects: Vec<BadType>,
+    /// clusters of the open bads, before the bad rows and columns are masked
+    pub open_clusters: Vec<Cluster>,
+    /// clusters of the short bads, empty when the short test was not run
+    pub short_clusters: Vec<Cluster>,
+    /// short bads along the lines of each orientation, empty when the short test was not run
+    pub short_lines: Vec<ShortLines>,
+}
 
+/// Short bads along one line of the short test
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
 pub struct ShortDiagonalStats {
     pub number_of_pixels_measured: u64,
     pub number_of_bad_shorts: u64
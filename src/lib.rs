@@ -0,0 +1,43 @@
+//! Measures open and short bad bump bonds from IDP images.
+//!
+//! `BumpBondAnalysis::analyse` runs both tests on the IDP files of one die
+//! and returns a `DieResult`; `IDPDecoder` and `IDPEncoder` read and write the images themselves.
+
+extern crate byteorder;
+#[macro_use]
+extern crate clap;
+extern crate regex;
+
+pub mod stream;
+pub mod image;
+pub mod decoder;
+pub mod encoder;
+pub mod utils;
+
+pub use decoder::{
+    IDPDecoder,
+    ImageDecoder
+};
+
+pub use encoder::IDPEncoder;
+
+pub use image::error::{
+    ImageError,
+    ImageResult
+};
+
+pub use image::other::{
+    DecodingResult,
+    EncodingBuffer,
+    DieResult,
+    OpenTestResult,
+    ShortTestResult
+};
+
+pub use utils::imageops::BumpBondAnalysis;
+pub use utils::deadband::DeadBand;
+pub use utils::pairing::Pairing;
+pub use utils::threshold::{
+    OpenThreshold,
+    ShortThreshold
+};
@@ -1,71 +1,430 @@
-extern crate byteorder;
-// extern crate getopts;
-#[macro_use]
-extern crate clap;
-extern crate regex;
+extern crate idp_tool;
 
-mod stream;
-mod image;
-mod decoder;
-mod utils;
-
-use std::path::Path;
+use std::fmt::Display;
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
 use std::iter::Iterator;
+use std::sync::Arc;
+
+use idp_tool::{
+    BumpBondAnalysis,
+    DeadBand,
+    Pairing
+};
 
-use utils::imageops::{
-    to_diff_pair
+use idp_tool::utils::binning::{
+    BinSpec,
+    YieldSummary
+};
+use idp_tool::utils::cache::{
+    cache_path,
+    recipe_key,
+    CacheEntry,
+    CacheMode,
+    CachedResult,
+    ResultsCache
+};
+use idp_tool::utils::clusters::{
+    write_clusters,
+    ClusterSummary
+};
+use idp_tool::utils::defectmap::write_die_images;
+use idp_tool::utils::discovery::{
+    find_dies,
+    Discovery
+};
+use idp_tool::utils::frames::FrameCombine;
+use idp_tool::utils::jobs::for_each_in_order;
+use idp_tool::utils::lot::{
+    wafer_dirs,
+    LotSummary,
+    WaferDir
+};
+use idp_tool::utils::shortlines::{
+    parse_orientations,
+    write_short_lines
+};
+use idp_tool::utils::threshold::{
+    OpenThreshold,
+    ShortThreshold
+};
+use idp_tool::utils::wafermap::{
+    check_metric,
+    write_wafer_maps
+};
+use idp_tool::utils::report::{
+    DieRecord,
+    DieSummary,
+    OutputFormat,
+    ReportWriter,
+    write_recipe
 };
 
-use utils::file::{
-    walk_test_dir,
-    extract_x_y_from_name
+use idp_tool::utils::file::{
+    CaptureName,
+    ChannelMode
 };
 
 //use utils::cmdline_options::{
-use utils::claptions::{
+use idp_tool::utils::claptions::{
     IDPToolOptions
 };
 
 fn main() {
-    let idp_tool_options = IDPToolOptions::make_new();
-    if "" == &idp_tool_options.test_directory {
-        return;
-    } 
+    let idp_tool_options = match IDPToolOptions::make_new() {
+        Ok( o ) => o,
+        Err( e ) => { print_error( e ); return; },
+    };
     idp_tool_options.print();
+
+    let dead_band = match DeadBand::from_spec( &idp_tool_options.dead_band ) {
+        Ok( d ) => d,
+        Err( e ) => { print_error( e ); return; },
+    };
+    let frame_combine = match idp_tool_options.frames.parse::<FrameCombine>() {
+        Ok( f ) => f,
+        Err( e ) => { print_error( e ); return; },
+    };
+    let pairing = match Pairing::from_spec( &idp_tool_options.pairing ) {
+        Ok( p ) => p,
+        Err( e ) => { print_error( e ); return; },
+    };
+    let channel = match idp_tool_options.channel.parse::<ChannelMode>() {
+        Ok( c ) => c,
+        Err( e ) => { print_error( e ); return; },
+    };
+    let open_threshold = match OpenThreshold::parse( &idp_tool_options.open_method, idp_tool_options.open_threshold ) {
+        Ok( t ) => t,
+        Err( e ) => { print_error( e ); return; },
+    };
+    let short_threshold = match ShortThreshold::parse( &idp_tool_options.short_method, idp_tool_options.short_threshold ) {
+        Ok( t ) => t,
+        Err( e ) => { print_error( e ); return; },
+    };
+    let cache_mode = match idp_tool_options.cache.parse::<CacheMode>() {
+        Ok( c ) => c,
+        Err( e ) => { print_error( e ); return; },
+    };
+    // the cache is kept next to the output, so that runs that write to different files do not share it
+    let cache_file = match ( cache_mode, idp_tool_options.output.as_ref() ) {
+        ( CacheMode::Off, _ ) => None,
+        ( _, Some( output ) ) => Some( cache_path( output ) ),
+        ( _, None ) => { print_error( "--cache needs --output, the cache is kept next to the output file" ); return; },
+    };
+    // the results of the recipe are looked up before the dead band goes into the analysis
+    let recipe = recipe_key( &idp_tool_options.recipe(), &dead_band );
+    let mut analysis = BumpBondAnalysis::new( open_threshold, short_threshold, idp_tool_options.ignore_edges, dead_band );
+    analysis.frame_combine = frame_combine;
+    analysis.pairing = pairing;
+    analysis.channel = channel;
+    analysis.bad_line_fraction = idp_tool_options.bad_line_fraction;
+    analysis.bad_line_run = idp_tool_options.bad_line_run;
+    analysis.short_lines = match parse_orientations( &idp_tool_options.short_lines ) {
+        Ok( o ) => o,
+        Err( e ) => { print_error( e ); return; },
+    };
+    analysis.short_line_fraction = idp_tool_options.short_line_fraction;
+    let format = match idp_tool_options.format.parse::<OutputFormat>() {
+        Ok( f ) => f,
+        Err( e ) => { print_error( e ); return; },
+    };
+    let spec = match idp_tool_options.spec {
+        Some( ref spec ) => match BinSpec::from_spec( spec ) {
+            Ok( s ) => Some( s ),
+            Err( e ) => { print_error( e ); return; },
+        },
+        None => None,
+    };
+    let wafer_metrics = idp_tool_options.wafer_metrics.split( ',' ).map( | m | m.trim().to_string() ).filter( | m | !m.is_empty() ).collect::<Vec<String>>();
+    for metric in wafer_metrics.iter() {
+        if let Err( e ) = check_metric( metric ) {
+            print_error( e );
+            return;
+        }
+    }
     
-    let input_dir = Path::new( &idp_tool_options.test_directory );
-    let mut file_sets = Vec::with_capacity(10);
-    let _walk_result = walk_test_dir( input_dir, &mut | entries | file_sets.push( entries ) );
-    //let _walk_result = walk_test_dir( input_dir, &mut | entries | if entries.len() >= 4usize { file_sets.push( entries ) } );
-    let _ = match _walk_result {
-      Ok( _ ) => {},
-      Err( e ) => { println!("The error is : {:?}" , e  ); return; },
+    let discovery = match Discovery::from_specs( &idp_tool_options.include, &idp_tool_options.exclude, &idp_tool_options.die_pattern ) {
+        Ok( d ) => d,
+        Err( e ) => { print_error( e ); return; },
+    };
+    let wafers = match wafer_dirs( &idp_tool_options.test_directories, &idp_tool_options.lots ) {
+        Ok( w ) => w,
+        Err( e ) => { print_error( e ); return; },
+    };
+    let mut wafer_dies: Vec<( WaferDir, Vec<( u32, u32, Vec<PathBuf> )> )> = Vec::with_capacity( wafers.len() );
+    for mut wafer in wafers.into_iter() {
+        let mut die_dirs = match find_dies( &wafer.dir, &discovery ) {
+            Ok( d ) => d,
+            Err( e ) => { print_error( e ); return; },
+        };
+        if die_dirs.is_empty() {
+            let _ = writeln!( io::stderr(), "warning: no dies found in {}", wafer.dir.display() );
+            continue;
+        }
+        wafer.name_from_captures( die_dirs.iter().flat_map( | die | die.paths.iter() ) );
+        if let Some( &( ref other, _ ) ) = wafer_dies.iter().find( | w | ( &w.0.lot, &w.0.wafer ) == ( &wafer.lot, &wafer.wafer ) ) {
+            let _ = writeln!( io::stderr(), "warning: {} and {} are both the wafer {} of the lot {}", other.dir.display(), wafer.dir.display(), wafer.wafer, wafer.lot );
+        }
+
+        // dies are numbered in the order of their x and y, whatever order the directories were listed in
+        die_dirs.sort_by( | a, b | ( a.x, a.y, &a.dir ).cmp( &( b.x, b.y, &b.dir ) ) );
+        for pair in die_dirs.windows( 2 ) {
+            if ( pair[0].x, pair[0].y ) == ( pair[1].x, pair[1].y ) {
+                let _ = writeln!( io::stderr(), "warning: {} and {} are both the die x{}y{}", pair[0].dir.display(), pair[1].dir.display(), pair[0].x, pair[0].y );
+            }
+        }
+        // only the C<code> captures of the chosen channel are kept, before any name is read
+        let dies = die_dirs.into_iter().map( | die | {
+            let paths = die.paths.into_iter().filter( | path | channel.uses( path ) ).collect();
+            ( die.x, die.y, paths )
+        } ).collect();
+        wafer_dies.push( ( wafer, dies ) );
+    }
+    // with several wafers, the files of each one go to <dir>/<lot>/<wafer> so that their dies do not overwrite each other
+    let several_wafers = wafer_dies.len() > 1;
+    let wafer_output = move | dir: &str, wafer: &WaferDir | if several_wafers {
+        Path::new( dir ).join( &wafer.lot ).join( &wafer.wafer )
+    } else {
+        PathBuf::from( dir )
+    };
+    let mut dies = Vec::new();
+    for ( w, &( ref wafer, ref wafer_dies ) ) in wafer_dies.iter().enumerate() {
+        let defect_maps = idp_tool_options.defect_maps.as_ref().map( | dir | wafer_output( dir, wafer ) );
+        let clusters = idp_tool_options.clusters.as_ref().map( | dir | wafer_output( dir, wafer ) );
+        for &( x, y, ref paths ) in wafer_dies.iter() {
+            dies.push( ( w, x, y, paths.clone(), defect_maps.clone(), clusters.clone() ) );
+        }
+    }
+
+    let out: Box<dyn Write> = match idp_tool_options.output {
+        Some( ref path ) => match File::create( path ) {
+            Ok( f ) => Box::new( BufWriter::new( f ) ),
+            Err( e ) => { print_error( format!( "{}: {}", path, e ) ); return; },
+        },
+        None => Box::new( BufWriter::new( io::stdout() ) ),
+    };
+    let mut report = ReportWriter::new( format, out );
+    if let Err( e ) = report.begin() {
+        print_error( e );
+        return;
+    }
+    // the effective recipe goes next to the output, so that each result can be analysed again with the same settings
+    let recipe_written = match idp_tool_options.output {
+        Some( ref path ) => {
+            let recipe_path = format!( "{}.recipe.toml", path );
+            File::create( &recipe_path ).and_then( | f | write_recipe( &mut BufWriter::new( f ), &idp_tool_options.recipe() ) )
+                                        .map_err( | e | format!( "{}: {}", recipe_path, e ) )
+        },
+        None => {
+            let mut err = io::stderr();
+            let _ = writeln!( err, "effective recipe:" );
+            write_recipe( &mut err, &idp_tool_options.recipe() ).map_err( | e | e.to_string() )
+        },
+    };
+    if let Err( e ) = recipe_written {
+        print_error( e );
+        return;
+    }
+
+    let cache = cache_file.as_ref().map( | cache_file | {
+        // a die is taken from the cache with -m or --clusters only when its files are the ones of this recipe
+        let latest_only = idp_tool_options.defect_maps.is_some() || idp_tool_options.clusters.is_some();
+        match ResultsCache::read( cache_file, cache_mode, recipe, latest_only ) {
+            Ok( c ) => c,
+            Err( e ) => {
+                let _ = writeln!( io::stderr(), "warning: starting the cache again, {}", e );
+                ResultsCache { mode: cache_mode, recipe: recipe, latest_only: latest_only, entries: Vec::new() }
+            },
+        }
+    } );
+
+    let analysis = Arc::new( analysis );
+    let lookup = Arc::new( cache.clone() );
+    let mut analysed = Vec::new();
+    let mut from_cache = 0;
+    let mut write_result = Ok( () );
+    // the wafer maps need every die, only their counts are kept
+    let mut records = Vec::new();
+    let mut yield_summary = YieldSummary::default();
+    // kept apart from the work, so that a die whose analysis panicked still gets its row
+    // so does the metadata of its captures
+    let positions = dies.iter().map( | &( w, x, y, ref paths, _, _ ) | ( w, x, y, earliest_capture( paths ) ) )
+        .collect::<Vec<( usize, u32, u32, Option<CaptureName> )>>();
+    for_each_in_order( dies, idp_tool_options.jobs,
+        move | ( _, x, y, paths, defect_maps, clusters ) | analyse_die( &analysis, &lookup, &defect_maps, &clusters, x, y, &paths ),
+        | i, result | {
+            let ( w, x, y, ref capture ) = positions[ i ];
+            let ( summary, error, cached ) = match result {
+                Ok( analysed ) => analysed,
+                Err( e ) => ( None, Some( format!( "the analysis failed unexpectedly: {}", e ) ), Cached::No ),
+            };
+            match cached {
+                Cached::Hit => from_cache += 1,
+                Cached::Analysed( entry ) => analysed.push( entry ),
+                Cached::No => {},
+            }
+            let mut record = DieRecord {
+                test_no : i,
+                lot     : wafer_dies[ w ].0.lot.clone(),
+                wafer   : wafer_dies[ w ].0.wafer.clone(),
+                x       : x,
+                y       : y,
+                capture : capture.clone(),
+                summary : summary,
+                bin     : None,
+                error   : error,
+            };
+            if let Some( ref spec ) = spec {
+                let bin = spec.bin( &record );
+                yield_summary.add( bin );
+                record.bin = Some( bin );
+            }
+            if write_result.is_ok() {
+                write_result = report.write_die( &record );
+            }
+            records.push( record );
+        }
+    );
+    if let Err( e ) = write_result.and_then( | _ | report.finish() ) {
+        print_error( e );
+        return;
+    }
+    if let ( &Some( ref cache ), &Some( ref cache_file ) ) = ( &cache, &cache_file ) {
+        let _ = writeln!( io::stderr(), "{} of {} dies taken from the cache {}", from_cache, records.len(), cache_file.display() );
+        if let Err( e ) = cache.write( cache_file, analysed ) {
+            print_error( e );
+        }
+    }
+    if let Some( ref dir ) = idp_tool_options.wafer_map {
+        for &( ref wafer, _ ) in wafer_dies.iter() {
+            let wafer_records = records.iter().filter( | r | ( &r.lot, &r.wafer ) == ( &wafer.lot, &wafer.wafer ) ).cloned().collect();
+            for metric in wafer_metrics.iter() {
+                if let Err( e ) = write_wafer_maps( &wafer_output( dir, wafer ), metric, &wafer_records ) {
+                    print_error( e );
+                }
+            }
+        }
+    }
+    if spec.is_some() {
+        let _ = yield_summary.write( &mut io::stderr() );
+    }
+    let _ = LotSummary::new( &wafer_metrics, &records ).write( &mut io::stderr() );
+    let _ = writeln!( io::stderr(), " \n\n DONE " );
+}
+
+fn print_error<E: Display>( e: E ) {
+    let _ = writeln!( io::stderr(), "The error is : {}", e );
+}
+
+// What the cache did for a die
+enum Cached {
+    /// the results were taken from it
+    Hit,
+    /// the die was analysed, with the entry to keep in the cache
+    Analysed( CacheEntry ),
+    /// no cache, or nothing to keep
+    No,
+}
+
+// Analyses one die and writes its images, unless the cache holds its results. Only the counts are kept,
+// so that the pixels of a die are freed as soon as it is done.
+// `paths` are the C<code> captures of the chosen channel.
+fn analyse_die( analysis: &BumpBondAnalysis, cache: &Option<ResultsCache>, defect_maps: &Option<PathBuf>, clusters: &Option<PathBuf>,
+                x: u32, y: u32, paths: &Vec<PathBuf> )
+    -> ( Option<DieSummary>, Option<String>, Cached ) {
+    if paths.is_empty() {
+        return ( None, Some( "the die has no C<v1><v2> capture of the channel chosen with -c".to_string() ), Cached::No );
+    }
+    // the die is known by its directory and its captures, a capture that can not be read is left to the analysis
+    let key = match *cache {
+        Some( ref cache ) => {
+            let dir = paths[0].parent().map( | d | d.canonicalize().unwrap_or( d.to_path_buf() ).to_string_lossy().into_owned() ).unwrap_or( String::new() );
+            cache.files_key( paths ).ok().map( | files | ( cache, dir, files ) )
+        },
+        None => None,
+    };
+    if let Some( ( cache, ref dir, files ) ) = key {
+        if let Some( entry ) = cache.get( dir, files ) {
+            return match entry.result {
+                Ok( ref r ) => ( Some( DieSummary {
+                    frames         : r.frames,
+                    open           : r.open,
+                    short          : r.short,
+                    open_clusters  : r.open_clusters.clone(),
+                    short_clusters : r.short_clusters.clone(),
+                } ), None, Cached::Hit ),
+                Err( ref e ) => ( None, Some( e.clone() ), Cached::Hit ),
+            };
+        }
+    }
+    let entry = | result: Result<CachedResult, String> | match key {
+        Some( ( cache, ref dir, files ) ) => Cached::Analysed( CacheEntry { dir: dir.clone(), recipe: cache.recipe, files: files, result: result } ),
+        None => Cached::No,
+    };
+    let result = match analysis.analyse( paths ) {
+        Ok( result ) => result,
+        Err( e ) => return ( None, Some( e.to_string() ), entry( Err( e.to_string() ) ) ),
+    };
+    let name = format!( "x{}y{}", x, y );
+    let written = match *defect_maps {
+        Some( ref dir ) => write_die_images( dir, &name, &result ).and_then( | _ |
+            if result.short_lines.is_empty() {
+                Ok( () )
+            } else {
+                write_short_lines( &dir.join( format!( "{}_short_lines.csv", name ) ), &result.short_lines )
+            } ),
+        None => Ok( () ),
+    }.and_then( | _ | match *clusters {
+        Some( ref dir ) => write_clusters( &dir.join( format!( "{}_clusters.csv", name ) ), &result.open_clusters, &result.short_clusters ),
+        None => Ok( () ),
+    } );
+    let error = match written {
+        Ok( _ )  => None,
+        Err( e ) => Some( e.to_string() ),
+    };
+    let summary = DieSummary {
+        frames         : result.frames,
+        open           : result.open,
+        short          : result.short,
+        open_clusters  : ClusterSummary::new( &result.open_clusters ),
+        short_clusters : ClusterSummary::new( &result.short_clusters ),
+    };
+    // a die whose images could not be written is analysed again next time
+    let cached = if error.is_none() {
+        entry( Ok( CachedResult {
+            frames         : summary.frames,
+            open           : summary.open,
+            short          : summary.short,
+            open_clusters  : summary.open_clusters.clone(),
+            short_clusters : summary.short_clusters.clone(),
+        } ) )
+    } else {
+        Cached::No
     };
+    ( Some( summary ), error, cached )
+}
 
-    println!(" test_no, case x, case y,  #open_bad_pixels, open_threshold, \
-               number_of_open_bads_in_bad_cols, number_of_open_bads_in_bad_rows, \
-               #open_bad_cols, #open_bad_rows, #short_bad_pixels, short_threshold, \
-               number_of_short_bads_not_in_bad_diagonals, number_of_bad_diagonals, number_of_adjacent_bad_diagonals, #measured_pixels" 
-            );
-    for ( i, file_set ) in file_sets.iter().filter( | &fset | fset.len() >= 4 ).enumerate() {
-        // println!( "The length os the fileset is: {:?} ", file_set.len() );
-        let diren = &file_set[0];
-        let path = diren.path();
-        let (x,y) = extract_x_y_from_name( &path );
-        let (
-           ( _open_diff_pixels_opt , _short_diff_pixels_opt ),
-            ( bad_opens, number_of_bad_columns, number_of_bad_rows, bad_shorts, threshold_for_shorts ,
-              num_measured, number_of_open_bads_in_bad_cols, number_of_open_bads_in_bad_rows, number_of_short_bads_not_in_bad_diagonals, number_of_bad_diagonals, number_of_adjacent_bad_diagonals )
-        ) = to_diff_pair( file_set, idp_tool_options.open_threshold, idp_tool_options.short_threshold, idp_tool_options.ignore_edges );
-        println!("{:?}, {:?}, {:?}, {:?}, {:?}, \
-                  {:?}, {:?}, \
-                  {:?}, {:?}, {:?}, {:?}, \
-                  {:?}, {:?}, {:?}, {:?}", 
-                   i,     x,    y,   bad_opens, idp_tool_options.open_threshold,
-                   number_of_open_bads_in_bad_cols, number_of_open_bads_in_bad_rows,
-                   number_of_bad_columns, number_of_bad_rows, bad_shorts, threshold_for_shorts,
-                   number_of_short_bads_not_in_bad_diagonals, number_of_bad_diagonals, number_of_adjacent_bad_diagonals, num_measured
-                );
+// The metadata in the output is the one of the earliest of the captures of a die.
+// A name that does not follow the naming of the captures only loses its metadata, the die is still analysed.
+fn earliest_capture( paths: &[PathBuf] ) -> Option<CaptureName> {
+    let mut earliest: Option<CaptureName> = None;
+    for path in paths.iter() {
+        let capture = match CaptureName::parse( path ) {
+            Ok( capture ) => capture,
+            Err( e ) => {
+                let _ = writeln!( io::stderr(), "warning: {}", e );
+                continue;
+            },
+        };
+        let is_earlier = match earliest {
+            Some( ref first ) => capture.timestamp < first.timestamp,
+            None => true,
+        };
+        if is_earlier {
+            earliest = Some( capture );
+        }
     }
-    println!( " \n\n DONE " );
-}  
+    earliest
+}
@@ -0,0 +1,162 @@
+use std::io;
+use std::io::Write;
+
+use image::error::{
+    ImageError,
+    ImageResult
+};
+
+use super::file::spec_lines;
+use super::report::{
+    DieRecord,
+    Value,
+    COLUMNS
+};
+
+/// Bin of a die
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub enum Bin {
+    /// every limit of the spec is met
+    Good,
+    /// too many bad rows or columns
+    RowColFail,
+    /// too many open bads
+    OpenFail,
+    /// too many short bads or bad lines
+    ShortFail,
+    /// the die could not be analysed
+    Error,
+}
+
+/// The bins in the order they are checked and reported
+pub const BINS: [Bin; 5] = [ Bin::Good, Bin::RowColFail, Bin::OpenFail, Bin::ShortFail, Bin::Error ];
+
+impl Bin {
+    pub fn name( &self ) -> &'static str {
+        match *self {
+            Bin::Good       => "GOOD",
+            Bin::RowColFail => "ROWCOL_FAIL",
+            Bin::OpenFail   => "OPEN_FAIL",
+            Bin::ShortFail  => "SHORT_FAIL",
+            Bin::Error      => "ERROR",
+        }
+    }
+
+    // the bin of a die that is over the limit of `field`
+    fn of_field( field: &str ) -> Option<Bin> {
+        match field {
+            "open_bad_cols" | "open_bad_rows" | "open_bad_col_segments" | "open_bad_row_segments" |
+            "open_bads_in_bad_cols" | "open_bads_in_bad_rows" => Some( Bin::RowColFail ),
+            "open_bad_pixels" | "open_clusters" | "open_largest_cluster" => Some( Bin::OpenFail ),
+            "short_bad_pixels" | "short_bads_not_in_bad_lines" | "bad_lines" | "adjacent_bad_lines" |
+            "short_clusters" | "short_largest_cluster" => Some( Bin::ShortFail ),
+            _ => None,
+        }
+    }
+}
+
+/// Largest value of a field for a die to be good
+#[derive(Debug, Clone)]
+pub struct Limit {
+    pub field : String,
+    pub max   : f64,
+    pub bin   : Bin,
+}
+
+/// Spec limits of a die.
+#[derive(Debug, Clone, Default)]
+pub struct BinSpec {
+    pub limits : Vec<Limit>,
+}
+
+impl BinSpec {
+    /// Parses spec limits, one per line of `spec_lines`. A limit is `<field> <max>`, e.g. `open_bad_pixels 1000`.
+    pub fn from_spec( spec: &str ) -> ImageResult<BinSpec> {
+        let mut limits = Vec::new();
+        for line in try!( spec_lines( spec ) ).iter() {
+            let line = &line[..];
+            let words: Vec<&str> = line.split_whitespace().collect();
+            if words.len() != 2 {
+                return Err( spec_error( line, "expected <field> <max>" ) );
+            }
+            let bin = match Bin::of_field( words[0] ) {
+                Some( bin ) => bin,
+                None => return Err( spec_error( line, &format!( "{:?} has no limit, use one of open_bad_pixels, open_bad_cols, open_bad_rows, \
+                                                                 open_bad_col_segments, open_bad_row_segments, \
+                                                                 open_bads_in_bad_cols, open_bads_in_bad_rows, open_clusters, open_largest_cluster, \
+                                                                 short_bad_pixels, short_bads_not_in_bad_lines, bad_lines, adjacent_bad_lines, \
+                                                                 short_clusters or short_largest_cluster", words[0] ) ) ),
+            };
+            let max = match words[1].parse::<f64>() {
+                Ok( max ) if !max.is_nan() => max,
+                _ => return Err( spec_error( line, &format!( "{:?} is not a valid number", words[1] ) ) ),
+            };
+            limits.push( Limit { field: words[0].to_string(), max: max, bin: bin } );
+        }
+        Ok( BinSpec { limits: limits } )
+    }
+
+    /// The bin of a die: ERROR when it could not be analysed, else the first bin in the order of `BINS`
+    /// with a limit that is exceeded, else GOOD.
+    pub fn bin( &self, record: &DieRecord ) -> Bin {
+        if record.summary.is_none() {
+            return Bin::Error;
+        }
+        let values = record.values();
+        let exceeded = | limit: &Limit | {
+            match COLUMNS.iter().position( | c | *c == limit.field ).map( | i | &values[i] ) {
+                Some( &Value::Int( v ) )   => v as f64 > limit.max,
+                Some( &Value::Float( v ) ) => v > limit.max,
+                _ => false,
+            }
+        };
+        for bin in BINS.iter() {
+            if self.limits.iter().any( | limit | limit.bin == *bin && exceeded( limit ) ) {
+                return *bin;
+            }
+        }
+        Bin::Good
+    }
+}
+
+fn spec_error( line: &str, msg: &str ) -> ImageError {
+    ImageError::FormatError( format!( "invalid spec limit {:?}: {}", line, msg ) )
+}
+
+/// Number of dies in each bin
+#[derive(Debug, Clone, Default)]
+pub struct YieldSummary {
+    /// in the order of `BINS`
+    pub counts : [usize; 5],
+}
+
+impl YieldSummary {
+    pub fn add( &mut self, bin: Bin ) {
+        if let Some( i ) = BINS.iter().position( | b | *b == bin ) {
+            self.counts[i] += 1;
+        }
+    }
+
+    pub fn total( &self ) -> usize {
+        self.counts.iter().fold( 0, | sum, count | sum + count )
+    }
+
+    /// Fraction of all the dies that are GOOD
+    pub fn yield_fraction( &self ) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => self.counts[0] as f64 / total as f64,
+        }
+    }
+
+    /// Writes the number and percentage of dies in each bin, and the yield.
+    pub fn write<W: Write>( &self, w: &mut W ) -> io::Result<()> {
+        let total = self.total();
+        try!( writeln!( w, "yield summary" ) );
+        for ( bin, count ) in BINS.iter().zip( self.counts.iter() ) {
+            let percent = if total == 0 { 0.0 } else { 100.0 * *count as f64 / total as f64 };
+            try!( writeln!( w, "  {:<12} {:>6} {:>6.1}%", bin.name(), count, percent ) );
+        }
+        writeln!( w, "  yield        {} of {} dies, {:.1}%", self.counts[0], total, 100.0 * self.yield_fraction() )
+    }
+}
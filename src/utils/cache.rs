@@ -0,0 +1,439 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::UNIX_EPOCH;
+
+use image::error::{
+    ImageError,
+    ImageResult
+};
+use image::other::{
+    OpenTestResult,
+    ShortTestResult
+};
+
+use super::clusters::ClusterSummary;
+use super::deadband::{
+    DeadBand,
+    DeadBandRegion
+};
+use super::report::Value;
+
+// first line of a cache file, a cache of another layout is started again
+const CACHE_HEADER: &'static str = "# idp_tool results cache 3";
+
+/// The options of the recipe that change the results of a die. The defect maps and the clusters are in,
+/// so that a die is analysed again when its files are asked for in another directory.
+pub const ANALYSIS_KEYS: &'static [&'static str] = &[
+    "open_threshold", "open_method", "short_threshold", "short_method",
+    "ignore_edges", "dead_band", "frames", "pairing", "channel",
+    "bad_line_fraction", "bad_line_run", "short_lines", "short_line_fraction",
+    "defect_maps", "clusters",
+];
+
+// the results of this many recipes are kept for each die, the ones analysed last
+const RECIPES_PER_DIE: usize = 8;
+
+/// How the captures of a die are recognised as unchanged
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub enum CacheMode {
+    /// no cache, every die is analysed
+    Off,
+    /// the same names, sizes and modification times
+    Mtime,
+    /// the same names and contents
+    Content,
+}
+
+impl FromStr for CacheMode {
+    type Err = String;
+
+    fn from_str( s: &str ) -> Result<CacheMode, String> {
+        match s.trim() {
+            "off"     => Ok( CacheMode::Off ),
+            "mtime"   => Ok( CacheMode::Mtime ),
+            "content" => Ok( CacheMode::Content ),
+            other     => Err( format!( "{:?} is not a cache mode, use off, mtime or content", other ) ),
+        }
+    }
+}
+
+/// The cache next to the output file, `<output>.cache`
+pub fn cache_path( output: &str ) -> PathBuf {
+    PathBuf::from( format!( "{}.cache", output ) )
+}
+
+/// What is kept of a die that was analysed, everything of the output that does not come from the file names
+#[derive(Debug, Clone)]
+pub struct CachedResult {
+    pub frames         : usize,
+    pub open           : OpenTestResult,
+    pub short          : ShortTestResult,
+    pub open_clusters  : ClusterSummary,
+    pub short_clusters : ClusterSummary,
+}
+
+/// The result of one die for one recipe
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    /// directory of the die
+    pub dir    : String,
+    pub recipe : u64,
+    /// key of the names and the sizes and times, or contents, of its captures
+    pub files  : u64,
+    /// the error when the die could not be analysed
+    pub result : Result<CachedResult, String>,
+}
+
+/// Results of earlier runs, by die, recipe and captures
+#[derive(Debug, Clone)]
+pub struct ResultsCache {
+    pub mode        : CacheMode,
+    pub recipe      : u64,
+    /// only the result of the recipe a die was analysed with last is used, as its defect maps and clusters are the ones of that recipe
+    pub latest_only : bool,
+    /// oldest first
+    pub entries     : Vec<CacheEntry>,
+}
+
+impl ResultsCache {
+    /// Reads the cache at `path`. A cache that does not exist yet is empty.
+    pub fn read( path: &Path, mode: CacheMode, recipe: u64, latest_only: bool ) -> ImageResult<ResultsCache> {
+        let mut cache = ResultsCache {
+            mode        : mode,
+            recipe      : recipe,
+            latest_only : latest_only,
+            entries     : Vec::new(),
+        };
+        if path.exists() {
+            cache.entries = try!( read_entries( path ).map_err( | e | e.for_file( path ) ) );
+        }
+        Ok( cache )
+    }
+
+    /// The key of the captures of a die, for this cache's mode
+    pub fn files_key( &self, paths: &[PathBuf] ) -> io::Result<u64> {
+        let mut paths = paths.to_vec();
+        paths.sort();
+        let mut hash = Fnv::new();
+        for path in paths.iter() {
+            hash.write_str( &path.file_name().map( | n | n.to_string_lossy().into_owned() ).unwrap_or( String::new() ) );
+            if self.mode == CacheMode::Content {
+                let mut f = try!( File::open( path ) );
+                let mut buffer = vec![ 0u8; 64 * 1024 ];
+                loop {
+                    let n = try!( f.read( &mut buffer ) );
+                    if n == 0 {
+                        break;
+                    }
+                    hash.write( &buffer[ ..n ] );
+                }
+            } else {
+                let metadata = try!( fs::metadata( path ) );
+                let modified = try!( metadata.modified() );
+                let since_epoch = modified.duration_since( UNIX_EPOCH ).unwrap_or( Default::default() );
+                hash.write_u64( metadata.len() );
+                hash.write_u64( since_epoch.as_secs() );
+                hash.write_u64( since_epoch.subsec_nanos() as u64 );
+            }
+        }
+        Ok( hash.finish() )
+    }
+
+    /// The result of the die in `dir` for the recipe of the run, when its captures have not changed
+    pub fn get( &self, dir: &str, files: u64 ) -> Option<&CacheEntry> {
+        let mut of_die = self.entries.iter().filter( | e | e.dir == dir );
+        let entry = if self.latest_only {
+            of_die.next_back()
+        } else {
+            of_die.find( | e | e.recipe == self.recipe )
+        };
+        entry.and_then( | e | if e.recipe == self.recipe && e.files == files { Some( e ) } else { None } )
+    }
+
+    /// Writes the entries with `analysed`, the dies analysed in this run, after them in place of their earlier results.
+    pub fn write( &self, path: &Path, analysed: Vec<CacheEntry> ) -> ImageResult<()> {
+        let replaced = analysed.iter().map( | a | ( &a.dir[..], a.recipe ) ).collect::<HashSet<( &str, u64 )>>();
+        // the entries of each die, oldest first, of which only the last recipes are kept
+        let mut by_die: HashMap<&str, VecDeque<&CacheEntry>> = HashMap::new();
+        let mut dies = Vec::new();
+        for entry in self.entries.iter().filter( | e | !replaced.contains( &( &e.dir[..], e.recipe ) ) ).chain( analysed.iter() ) {
+            let of_die = by_die.entry( &entry.dir[..] ).or_default();
+            if of_die.is_empty() {
+                dies.push( &entry.dir[..] );
+            }
+            if of_die.len() == RECIPES_PER_DIE {
+                of_die.pop_front();
+            }
+            of_die.push_back( entry );
+        }
+        let kept = dies.iter().flat_map( | dir | by_die[ dir ].iter().cloned() ).collect::<Vec<&CacheEntry>>();
+        write_entries( path, &kept ).map_err( | e | e.for_file( path ) )
+    }
+}
+
+/// The key of the recipe of a run: the analysis options, the content of the files they name,
+/// the masks of the dead band and the version of the tool
+pub fn recipe_key( recipe: &[( &str, Value )], dead_band: &DeadBand ) -> u64 {
+    let mut hash = Fnv::new();
+    hash.write_str( env!( "CARGO_PKG_VERSION" ) );
+    for &( name, ref value ) in recipe.iter().filter( | &&( name, _ ) | ANALYSIS_KEYS.contains( &name ) ) {
+        hash.write_str( name );
+        hash.write_str( &format!( "{:?}", value ) );
+        if let Value::Text( ref text ) = *value {
+            let mut content = Vec::new();
+            if Path::new( text ).is_file() && File::open( text ).and_then( | mut f | f.read_to_end( &mut content ) ).is_ok() {
+                hash.write( &content );
+            }
+        }
+    }
+    for region in dead_band.regions.iter() {
+        if let DeadBandRegion::Mask { ref dead, .. } = *region {
+            hash.write( &dead.iter().map( | d | *d as u8 ).collect::<Vec<u8>>() );
+        }
+    }
+    hash.finish()
+}
+
+// 64 bit FNV-1a, enough to tell changed files apart without another dependency
+struct Fnv( u64 );
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv( 0xcbf29ce484222325 )
+    }
+
+    fn write( &mut self, bytes: &[u8] ) {
+        for b in bytes.iter() {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul( 0x100000001b3 );
+        }
+    }
+
+    fn write_u64( &mut self, v: u64 ) {
+        let bytes = ( 0..8 ).map( | i | ( v >> ( 8 * i ) ) as u8 ).collect::<Vec<u8>>();
+        self.write( &bytes );
+    }
+
+    // with its length, so that "ab","c" and "a","bc" differ
+    fn write_str( &mut self, s: &str ) {
+        self.write_u64( s.len() as u64 );
+        self.write( s.as_bytes() );
+    }
+
+    fn finish( &self ) -> u64 {
+        self.0
+    }
+}
+
+// One entry per line, tab separated: dir, recipe, files, then `ok` and the counts or `error` and the message.
+// The dir and the message have their tabs, new lines and backslashes escaped.
+fn write_entries( path: &Path, entries: &Vec<&CacheEntry> ) -> ImageResult<()> {
+    if let Some( dir ) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            try!( fs::create_dir_all( dir ) );
+        }
+    }
+    let f = try!( File::create( path ) );
+    let mut w = BufWriter::new( f );
+    try!( writeln!( w, "{}", CACHE_HEADER ) );
+    for entry in entries.iter() {
+        try!( write!( w, "{}\t{:016x}\t{:016x}", escape( &entry.dir ), entry.recipe, entry.files ) );
+        match entry.result {
+            Ok( ref r ) => {
+                let ( o, s ) = ( &r.open, &r.short );
+                try!( writeln!( w, "\tok\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                                o.bad_opens, o.open_threshold.map( | t | t.to_string() ).unwrap_or( String::new() ), o.number_of_bad_columns, o.number_of_bad_rows,
+                                o.number_of_bad_col_segments, o.number_of_bad_row_segments,
+                                o.number_of_open_bads_in_bad_cols, o.number_of_open_bads_in_bad_rows, o.number_of_pixels_measured,
+                                s.bad_shorts, s.threshold_for_shorts, s.number_of_short_bads_not_in_bad_lines,
+                                s.number_of_bad_lines, s.number_of_adjacent_bad_lines,
+                                cluster_text( &r.open_clusters ), cluster_text( &r.short_clusters ), r.frames ) );
+            },
+            Err( ref e ) => try!( writeln!( w, "\terror\t{}", escape( e ) ) ),
+        }
+    }
+    try!( w.flush() );
+    Ok( () )
+}
+
+fn read_entries( path: &Path ) -> ImageResult<Vec<CacheEntry>> {
+    let f = try!( File::open( path ) );
+    let mut lines = BufReader::new( f ).lines();
+    match lines.next() {
+        Some( Ok( ref header ) ) if header == CACHE_HEADER => {},
+        Some( Err( e ) ) => return Err( ImageError::IoError( e ) ),
+        _ => return Err( ImageError::FormatError( "not a results cache of this version of idp_tool".to_string() ) ),
+    }
+    let mut entries = Vec::new();
+    for ( n, line ) in lines.enumerate() {
+        let line = try!( line );
+        match parse_entry( &line ) {
+            Some( entry ) => entries.push( entry ),
+            None => return Err( ImageError::FormatError( format!( "line {} is not a cache entry", n + 2 ) ) ),
+        }
+    }
+    Ok( entries )
+}
+
+fn parse_entry( line: &str ) -> Option<CacheEntry> {
+    let fields = line.split( '\t' ).collect::<Vec<&str>>();
+    if fields.len() < 5 {
+        return None;
+    }
+    let recipe = match u64::from_str_radix( fields[1], 16 ) { Ok( v ) => v, Err( _ ) => return None };
+    let files = match u64::from_str_radix( fields[2], 16 ) { Ok( v ) => v, Err( _ ) => return None };
+    let result = match ( fields[3], fields.len() ) {
+        ( "error", 5 ) => Err( unescape( fields[4] ) ),
+        ( "ok", 21 ) => {
+            let f = &fields[ 4.. ];
+            let open = OpenTestResult {
+                bad_opens                       : match f[0].parse() { Ok( v ) => v, Err( _ ) => return None },
+                open_threshold                  : match f[1] { "" => None, t => match t.parse() { Ok( v ) => Some( v ), Err( _ ) => return None } },
+                number_of_bad_columns           : match f[2].parse() { Ok( v ) => v, Err( _ ) => return None },
+                number_of_bad_rows              : match f[3].parse() { Ok( v ) => v, Err( _ ) => return None },
+                number_of_bad_col_segments      : match f[4].parse() { Ok( v ) => v, Err( _ ) => return None },
+                number_of_bad_row_segments      : match f[5].parse() { Ok( v ) => v, Err( _ ) => return None },
+                number_of_open_bads_in_bad_cols : match f[6].parse() { Ok( v ) => v, Err( _ ) => return None },
+                number_of_open_bads_in_bad_rows : match f[7].parse() { Ok( v ) => v, Err( _ ) => return None },
+                number_of_pixels_measured       : match f[8].parse() { Ok( v ) => v, Err( _ ) => return None },
+            };
+            let short = ShortTestResult {
+                bad_shorts                            : match f[9].parse() { Ok( v ) => v, Err( _ ) => return None },
+                threshold_for_shorts                  : match f[10].parse() { Ok( v ) => v, Err( _ ) => return None },
+                number_of_short_bads_not_in_bad_lines : match f[11].parse() { Ok( v ) => v, Err( _ ) => return None },
+                number_of_bad_lines                   : match f[12].parse() { Ok( v ) => v, Err( _ ) => return None },
+                number_of_adjacent_bad_lines          : match f[13].parse() { Ok( v ) => v, Err( _ ) => return None },
+            };
+            let ( open_clusters, short_clusters ) = match ( parse_clusters( f[14] ), parse_clusters( f[15] ) ) {
+                ( Some( o ), Some( s ) ) => ( o, s ),
+                _ => return None,
+            };
+            let frames = match f[16].parse() { Ok( v ) => v, Err( _ ) => return None };
+            Ok( CachedResult { frames: frames, open: open, short: short, open_clusters: open_clusters, short_clusters: short_clusters } )
+        },
+        _ => return None,
+    };
+    Some( CacheEntry { dir: unescape( fields[0] ), recipe: recipe, files: files, result: result } )
+}
+
+// `<clusters> <largest> <histogram separated by ,>`
+fn cluster_text( summary: &ClusterSummary ) -> String {
+    let histogram = summary.size_histogram.iter().map( | c | c.to_string() ).collect::<Vec<String>>();
+    format!( "{} {} {}", summary.number_of_clusters, summary.largest_cluster, histogram.join( "," ) )
+}
+
+fn parse_clusters( text: &str ) -> Option<ClusterSummary> {
+    let words = text.split( ' ' ).collect::<Vec<&str>>();
+    if words.len() != 3 {
+        return None;
+    }
+    let histogram = words[2].split( ',' ).filter( | w | !w.is_empty() ).map( | w | w.parse::<usize>().ok() ).collect::<Option<Vec<usize>>>();
+    match ( words[0].parse::<usize>(), words[1].parse::<usize>(), histogram ) {
+        ( Ok( n ), Ok( largest ), Some( histogram ) ) => Some( ClusterSummary { number_of_clusters: n, largest_cluster: largest, size_histogram: histogram } ),
+        _ => None,
+    }
+}
+
+fn escape( text: &str ) -> String {
+    text.replace( "\\", "\\\\" ).replace( "\t", "\\t" ).replace( "\n", "\\n" ).replace( "\r", "\\r" )
+}
+
+fn unescape( text: &str ) -> String {
+    let mut unescaped = String::with_capacity( text.len() );
+    let mut chars = text.chars();
+    while let Some( c ) = chars.next() {
+        if c != '\\' {
+            unescaped.push( c );
+            continue;
+        }
+        match chars.next() {
+            Some( 't' ) => unescaped.push( '\t' ),
+            Some( 'n' ) => unescaped.push( '\n' ),
+            Some( 'r' ) => unescaped.push( '\r' ),
+            Some( c )   => unescaped.push( c ),
+            None        => unescaped.push( '\\' ),
+        }
+    }
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use image::other::{
+        OpenTestResult,
+        ShortTestResult
+    };
+
+    use super::{
+        CachedResult,
+        CacheEntry,
+        read_entries,
+        write_entries
+    };
+    use utils::clusters::ClusterSummary;
+
+    fn result( open_threshold: Option<f32>, short_threshold: f32 ) -> CachedResult {
+        CachedResult {
+            frames : 3,
+            open : OpenTestResult {
+                bad_opens                       : 12,
+                open_threshold                  : open_threshold,
+                number_of_bad_columns           : 1,
+                number_of_bad_rows              : 2,
+                number_of_bad_col_segments      : 3,
+                number_of_bad_row_segments      : 4,
+                number_of_open_bads_in_bad_cols : 5,
+                number_of_open_bads_in_bad_rows : 6,
+                number_of_pixels_measured       : 5050,
+            },
+            short : ShortTestResult {
+                bad_shorts                            : 7,
+                threshold_for_shorts                  : short_threshold,
+                number_of_short_bads_not_in_bad_lines : 8,
+                number_of_bad_lines                   : 9,
+                number_of_adjacent_bad_lines          : 10,
+            },
+            open_clusters  : ClusterSummary { number_of_clusters: 3, largest_cluster: 9, size_histogram: vec![ 1, 0, 1, 1 ] },
+            short_clusters : ClusterSummary::default(),
+        }
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let entries = vec![
+            CacheEntry { dir: "/data/lot1/x1y2".to_string(), recipe: 1, files: 0xffffffffffffffff, result: Ok( result( Some( 0.3 ), 0.375 ) ) },
+            CacheEntry { dir: "C:\\data\\tab\there\nline".to_string(), recipe: 2, files: 0, result: Ok( result( None, ::std::f32::NAN ) ) },
+            CacheEntry { dir: "x3y4\\".to_string(), recipe: 3, files: 42, result: Err( "Truncated data:\tx3y4\\a.IDP\nunexpected end".to_string() ) },
+        ];
+        // a path of its own, so that runs of the tests at the same time do not share the file
+        let path = env::temp_dir().join( format!( "idp_tool_entries_round_trip_{}.cache", process::id() ) );
+        write_entries( &path, &entries.iter().collect() ).unwrap();
+        let read = read_entries( &path ).unwrap();
+        let _ = fs::remove_file( &path );
+
+        assert_eq!( read.len(), entries.len() );
+        for ( r, e ) in read.iter().zip( entries.iter() ) {
+            assert_eq!( ( &r.dir, r.recipe, r.files ), ( &e.dir, e.recipe, e.files ) );
+            match ( &r.result, &e.result ) {
+                ( &Ok( ref r ), &Ok( ref e ) ) => {
+                    assert_eq!( r.open, e.open );
+                    assert_eq!( ( r.short.bad_shorts, r.short.number_of_short_bads_not_in_bad_lines, r.short.number_of_bad_lines, r.short.number_of_adjacent_bad_lines ),
+                                ( e.short.bad_shorts, e.short.number_of_short_bads_not_in_bad_lines, e.short.number_of_bad_lines, e.short.number_of_adjacent_bad_lines ) );
+                    assert!( r.short.threshold_for_shorts == e.short.threshold_for_shorts || ( r.short.threshold_for_shorts.is_nan() && e.short.threshold_for_shorts.is_nan() ) );
+                    assert_eq!( ( &r.open_clusters, &r.short_clusters ), ( &e.open_clusters, &e.short_clusters ) );
+                },
+                ( &Err( ref r ), &Err( ref e ) ) => assert_eq!( r, e ),
+                _ => panic!( "an ok entry and an error entry" ),
+            }
+        }
+    }
+}
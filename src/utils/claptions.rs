@@ -1,11 +1,43 @@
 use clap::{Arg, App};
 use std::env;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use utils::deadband::DEFAULT_DEAD_BAND;
+use utils::discovery::DEFAULT_DIE_PATTERN;
+use utils::recipe::Recipe;
+use utils::report::Value;
 
 pub struct IDPToolOptions {
-    pub test_directory  : String,
+    pub test_directories : Vec<String>,
+    pub lots            : Vec<String>,
     pub open_threshold  : f32,
+    pub open_method     : String,
     pub short_threshold : f32,
+    pub short_method    : String,
     pub ignore_edges    : usize,
+    pub dead_band       : String,
+    pub defect_maps     : Option<String>,
+    pub clusters        : Option<String>,
+    pub frames          : String,
+    pub pairing         : String,
+    pub channel         : String,
+    pub jobs            : usize,
+    pub format          : String,
+    pub output          : Option<String>,
+    pub wafer_map       : Option<String>,
+    pub wafer_metrics   : String,
+    pub spec            : Option<String>,
+    pub bad_line_fraction : f32,
+    pub bad_line_run    : usize,
+    pub short_lines     : String,
+    pub short_line_fraction : f32,
+    pub include         : String,
+    pub exclude         : String,
+    pub die_pattern     : String,
+    pub cache           : String,
 }
 
 
@@ -18,20 +50,80 @@ impl IDPToolOptions {
     //         ignore_edges    : 0usize,
     //     }
     // }
-    pub fn make_new() -> IDPToolOptions {
+    /// The options of the command line, the ones it does not give are taken from the recipe given with --recipe
+    pub fn make_new() -> Result<IDPToolOptions, String> {
        from_cmdline_options()
    }
 
+    /// The effective recipe: every option a recipe can set, with the value used.
+    /// A path that is not given is Null.
+    pub fn recipe( &self ) -> Vec<( &'static str, Value )> {
+        let text = | v: &String | Value::Text( v.clone() );
+        let path = | v: &Option<String> | v.as_ref().map( | p | Value::Text( p.clone() ) ).unwrap_or( Value::Null );
+        vec![
+            ( "open_threshold",      Value::from_f32( self.open_threshold ) ),
+            ( "open_method",         text( &self.open_method ) ),
+            ( "short_threshold",     Value::from_f32( self.short_threshold ) ),
+            ( "short_method",        text( &self.short_method ) ),
+            ( "ignore_edges",        Value::Int( self.ignore_edges as i64 ) ),
+            ( "dead_band",           text( &self.dead_band ) ),
+            ( "frames",              text( &self.frames ) ),
+            ( "pairing",             text( &self.pairing ) ),
+            ( "channel",             text( &self.channel ) ),
+            ( "bad_line_fraction",   Value::from_f32( self.bad_line_fraction ) ),
+            ( "bad_line_run",        Value::Int( self.bad_line_run as i64 ) ),
+            ( "short_lines",         text( &self.short_lines ) ),
+            ( "short_line_fraction", Value::from_f32( self.short_line_fraction ) ),
+            ( "include",             text( &self.include ) ),
+            ( "exclude",             text( &self.exclude ) ),
+            ( "die_pattern",         text( &self.die_pattern ) ),
+            ( "spec",                path( &self.spec ) ),
+            ( "jobs",                Value::Int( self.jobs as i64 ) ),
+            ( "format",              text( &self.format ) ),
+            ( "output",              path( &self.output ) ),
+            ( "defect_maps",         path( &self.defect_maps ) ),
+            ( "clusters",            path( &self.clusters ) ),
+            ( "wafer_map",           path( &self.wafer_map ) ),
+            ( "wafer_metrics",       text( &self.wafer_metrics ) ),
+            ( "cache",               text( &self.cache ) ),
+        ]
+    }
+
+    // to stderr, so that only the results go to the output
     pub fn print( &self) {
-        println!("The following test options will be used: " );
-        println!("test_directory  : {:?}", self.test_directory  );
-        println!("open_threshold  : {:?}", self.open_threshold  );
-        println!("short_threshold : {:?}", self.short_threshold );
-        println!("ignore_edges    : {:?}", self.ignore_edges    );
+        let mut err = io::stderr();
+        let _ = writeln!( err, "The following test options will be used: " );
+        let _ = writeln!( err, "test_directories : {:?}", self.test_directories );
+        let _ = writeln!( err, "lots            : {:?}", self.lots            );
+        let _ = writeln!( err, "open_threshold  : {:?}", self.open_threshold  );
+        let _ = writeln!( err, "open_method     : {:?}", self.open_method     );
+        let _ = writeln!( err, "short_threshold : {:?}", self.short_threshold );
+        let _ = writeln!( err, "short_method    : {:?}", self.short_method    );
+        let _ = writeln!( err, "ignore_edges    : {:?}", self.ignore_edges    );
+        let _ = writeln!( err, "dead_band       : {:?}", self.dead_band       );
+        let _ = writeln!( err, "defect_maps     : {:?}", self.defect_maps     );
+        let _ = writeln!( err, "clusters        : {:?}", self.clusters        );
+        let _ = writeln!( err, "frames          : {:?}", self.frames          );
+        let _ = writeln!( err, "pairing         : {:?}", self.pairing         );
+        let _ = writeln!( err, "channel         : {:?}", self.channel         );
+        let _ = writeln!( err, "jobs            : {:?}", self.jobs            );
+        let _ = writeln!( err, "format          : {:?}", self.format          );
+        let _ = writeln!( err, "output          : {:?}", self.output          );
+        let _ = writeln!( err, "wafer_map       : {:?}", self.wafer_map       );
+        let _ = writeln!( err, "wafer_metrics   : {:?}", self.wafer_metrics   );
+        let _ = writeln!( err, "spec            : {:?}", self.spec            );
+        let _ = writeln!( err, "bad_line_fraction : {:?}", self.bad_line_fraction );
+        let _ = writeln!( err, "bad_line_run    : {:?}", self.bad_line_run    );
+        let _ = writeln!( err, "short_lines     : {:?}", self.short_lines     );
+        let _ = writeln!( err, "short_line_fraction : {:?}", self.short_line_fraction );
+        let _ = writeln!( err, "include         : {:?}", self.include         );
+        let _ = writeln!( err, "exclude         : {:?}", self.exclude         );
+        let _ = writeln!( err, "die_pattern     : {:?}", self.die_pattern     );
+        let _ = writeln!( err, "cache           : {:?}", self.cache           );
     }
 }
 
-fn from_cmdline_options() -> IDPToolOptions {
+fn from_cmdline_options() -> Result<IDPToolOptions, String> {
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
     let matches = App::new(&program)
@@ -40,10 +132,18 @@ fn from_cmdline_options() -> IDPToolOptions {
                           .arg(Arg::with_name("test_directory")
                                .short("t")
                                .long("test_dir")
-                               .help("Test area with each sub dir containing idp images.")
-                               .required( true )
+                               .help("Wafer test area with each sub dir containing idp images. Can be given several times, one per wafer.")
+                               .required(false)
                                .takes_value(true)
+                               .multiple(true)
                            )
+                          .arg(Arg::with_name("lot")
+                               .long("lot")
+                               .help("Lot directory with a wafer test area in each sub dir. Can be given several times, one per lot.")
+                               .required(false)
+                               .takes_value(true)
+                               .multiple(true)
+                               )
                           .arg(Arg::with_name("open_threshold")
                                .short("o")
                                .long("open_threshold")
@@ -51,6 +151,12 @@ fn from_cmdline_options() -> IDPToolOptions {
                                .required(false)
                                .takes_value(true)
                                )
+                          .arg(Arg::with_name("open_method")
+                               .long("open_method")
+                               .help("Threshold of the open test: global, separable_median <radius> <fraction>, row <fraction> or column <fraction>. Default: global, with -o as the threshold.")
+                               .required(false)
+                               .takes_value(true)
+                               )
                           .arg(Arg::with_name("short_threshold")
                                .short("s")
                                .long("short_threshold")
@@ -58,6 +164,12 @@ fn from_cmdline_options() -> IDPToolOptions {
                                .required(false)
                                .takes_value(true)
                                )
+                          .arg(Arg::with_name("short_method")
+                               .long("short_method")
+                               .help("Threshold of the short test: median [<fraction>], percentile <p>, mad <k> or absolute <value>. Default: median, with -s as the fraction.")
+                               .required(false)
+                               .takes_value(true)
+                               )
                           .arg(Arg::with_name("ignore_edges")
                                .short("i")
                                .long("ignore_edges")
@@ -65,16 +177,223 @@ fn from_cmdline_options() -> IDPToolOptions {
                                .required(false)
                                .takes_value(true)
                                )
+                          .arg(Arg::with_name("dead_band")
+                               .short("d")
+                               .long("dead_band")
+                               .help("Dead band geometry: a file with one region per line, or regions separated by ';'.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("frames")
+                               .short("f")
+                               .long("frames")
+                               .help("How to combine the frames of files with several frames: mean or median.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("pairing")
+                               .short("p")
+                               .long("pairing")
+                               .help("Pairing rules: a file with one rule per line, or rules separated by ';'. Default: open v1 == v2, short v1 != v2.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("channel")
+                               .short("c")
+                               .long("channel")
+                               .help("Channel to build the pairs from: reset, signal or cds ( signal - reset ).")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("jobs")
+                               .short("j")
+                               .long("jobs")
+                               .help("Number of dies to analyse at the same time.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("defect_maps")
+                               .short("m")
+                               .long("defect_maps")
+                               .help("Directory to write the defect map and the open/short differences of each die to.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("clusters")
+                               .long("clusters")
+                               .help("Directory to write the clusters of each die to. Default: the one of -m.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("format")
+                               .long("format")
+                               .help("Output format: csv, json or jsonl.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("output")
+                               .long("output")
+                               .help("File to write the results to instead of stdout.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("wafer_map")
+                               .long("wafer_map")
+                               .help("Directory to write wafer maps of the dies to, as text, csv and image.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("cache")
+                               .long("cache")
+                               .help("Keep the results of the dies next to the --output file and only analyse the new or changed ones: off, mtime or content. Default: off.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("wafer_metrics")
+                               .long("wafer_metrics")
+                               .help("Fields shown on the wafer maps, separated by ','. Default: open_bad_pixels,short_bad_pixels.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("spec")
+                               .long("spec")
+                               .help("Spec limits to bin the dies with: a file with one limit per line, or limits separated by ';'.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("bad_line_fraction")
+                               .long("bad_line_fraction")
+                               .help("Fraction of the measured pixels of a row or column that must be open bads for it to be bad. Default: 0.5.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("bad_line_run")
+                               .long("bad_line_run")
+                               .help("Length of the runs of open bads along a row or column that are bad segments. Default: 0, no segments.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("short_line_fraction")
+                               .long("short_line_fraction")
+                               .help("Fraction of the measured pixels of a line of the short test that must be short bads for it to be bad. Default: 0.5.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("recipe")
+                               .long("recipe")
+                               .help("Recipe file with a value for any of the options but -t and --lot, as flat key = value TOML or a JSON object when it ends in .json. The command line wins over it.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("include")
+                               .long("include")
+                               .help("Die directories to analyse, globs on their path under the test dir separated by ';', e.g. lot1/**. Default: all.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("exclude")
+                               .long("exclude")
+                               .help("Directories to leave out with everything below them, globs on their path under the test dir separated by ';'.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("die_pattern")
+                               .long("die_pattern")
+                               .help("Regexes for the names of die directories, with the groups (?P<x>...) and (?P<y>...), separated by ';'. Default: x<x>y<y>.")
+                               .required(false)
+                               .takes_value(true)
+                               )
+                          .arg(Arg::with_name("short_lines")
+                               .long("short_lines")
+                               .help("Lines the short bads are counted along, separated by ',': row, column, diagonal or anti_diagonal. Default: anti_diagonal.")
+                               .required(false)
+                               .takes_value(true)
+                               )
                           .get_matches();
-    let test_directory  = matches.value_of( "test_directory"  ).unwrap_or( "test" ).to_string();
-    let open_threshold  = matches.value_of( "open_threshold"  ).unwrap_or( "0.3"  ).trim().parse::<f32  >().ok().unwrap_or( 0.0f32 ); 
-    let short_threshold = matches.value_of( "short_threshold" ).unwrap_or( "0.75" ).trim().parse::<f32  >().ok().unwrap_or( 0.0f32 ); 
-    let ignore_edges    = matches.value_of( "ignore_edges"    ).unwrap_or( "0"    ).trim().parse::<usize>().ok().unwrap_or( 0usize );
+    // an option on the command line wins over the recipe
+    let recipe = match matches.value_of( "recipe" ) {
+        Some( path ) => try!( Recipe::read( Path::new( path ) ).map_err( | e | e.to_string() ) ),
+        None => Recipe::default(),
+    };
+    let value_of = | name: &str | matches.value_of( name ).or( recipe.get( name ) ).map( | v | v.to_string() );
+    let source_of = | name: &str | if matches.value_of( name ).is_some() { format!( "--{}", name ) } else { format!( "{} in the recipe", name ) };
+    let number = | name: &str | ( value_of( name ), source_of( name ) );
+
+    let values_of = | name: &str | matches.values_of( name ).map( | values | values.into_iter().map( | v | v.to_string() ).collect::<Vec<String>>() ).unwrap_or( Vec::new() );
+    let test_directories = values_of( "test_directory" );
+    let lots            = values_of( "lot" );
+    if test_directories.is_empty() && lots.is_empty() {
+        return Err( "no dies to analyse, give a wafer test area with -t or a lot with --lot".to_string() );
+    }
+    if test_directories.iter().chain( lots.iter() ).any( | dir | dir.trim().is_empty() ) {
+        return Err( "a directory given with -t or --lot is empty".to_string() );
+    }
+    let open_threshold  = try!( number_option( number( "open_threshold" ), 0.3f32, | v: f32 | v.is_finite() && v >= 0.0, "a number of at least 0" ) );
+    let open_method     = value_of( "open_method"     ).unwrap_or( "global".to_string() );
+    let short_threshold = try!( number_option( number( "short_threshold" ), 0.75f32, | v: f32 | v.is_finite() && v >= 0.0, "a number of at least 0" ) );
+    let short_method    = value_of( "short_method"    ).unwrap_or( "median".to_string() );
+    let ignore_edges    = try!( number_option( number( "ignore_edges" ), 0usize, | _ | true, "a whole number of rows and columns" ) );
+    let dead_band       = value_of( "dead_band"       ).unwrap_or( DEFAULT_DEAD_BAND.to_string() );
+    let defect_maps     = value_of( "defect_maps"     );
+    let clusters        = value_of( "clusters"        ).or( defect_maps.clone() );
+    let frames          = value_of( "frames"          ).unwrap_or( "mean".to_string() );
+    let pairing         = value_of( "pairing"         ).unwrap_or( "".to_string()     );
+    let channel         = value_of( "channel"         ).unwrap_or( "reset".to_string() );
+    let jobs            = try!( number_option( number( "jobs" ), 1usize, | v: usize | v >= 1, "a whole number of at least 1" ) );
+    let format          = value_of( "format"          ).unwrap_or( "csv".to_string()  );
+    let output          = value_of( "output"          );
+    let wafer_map       = value_of( "wafer_map"       );
+    let spec            = value_of( "spec"            );
+    let wafer_metrics   = value_of( "wafer_metrics"   ).unwrap_or( "open_bad_pixels,short_bad_pixels".to_string() );
+    let cache           = value_of( "cache"           ).unwrap_or( "off".to_string()  );
+    let bad_line_fraction = try!( number_option( number( "bad_line_fraction" ), 0.5f32, | v: f32 | v >= 0.0 && v < 1.0, "a fraction of at least 0 and less than 1" ) );
+    let bad_line_run    = try!( number_option( number( "bad_line_run" ), 0usize, | _ | true, "a whole number of pixels" ) );
+    let short_lines     = value_of( "short_lines"     ).unwrap_or( "anti_diagonal".to_string() );
+    let include         = value_of( "include"         ).unwrap_or( "".to_string()     );
+    let exclude         = value_of( "exclude"         ).unwrap_or( "".to_string()     );
+    let die_pattern     = value_of( "die_pattern"     ).unwrap_or( DEFAULT_DIE_PATTERN.to_string() );
+    let short_line_fraction = try!( number_option( number( "short_line_fraction" ), 0.5f32, | v: f32 | v >= 0.0 && v < 1.0, "a fraction of at least 0 and less than 1" ) );
 
-    IDPToolOptions {
-        test_directory  : test_directory,
+    Ok( IDPToolOptions {
+        test_directories : test_directories,
+        lots            : lots,
         open_threshold  : open_threshold,
+        open_method     : open_method,
         short_threshold : short_threshold,
+        short_method    : short_method,
         ignore_edges    : ignore_edges,
+        dead_band       : dead_band,
+        defect_maps     : defect_maps,
+        clusters        : clusters,
+        frames          : frames,
+        pairing         : pairing,
+        channel         : channel,
+        jobs            : jobs,
+        format          : format,
+        output          : output,
+        wafer_map       : wafer_map,
+        wafer_metrics   : wafer_metrics,
+        spec            : spec,
+        bad_line_fraction : bad_line_fraction,
+        bad_line_run    : bad_line_run,
+        short_lines     : short_lines,
+        short_line_fraction : short_line_fraction,
+        include         : include,
+        exclude         : exclude,
+        die_pattern     : die_pattern,
+        cache           : cache,
+    } )
+}
+
+// the value of a number option, `default` when it is not given.
+// A value that is not a number of the type, or that is not `valid`, is an error rather than a silent default,
+// e.g. -o 0,3 would otherwise be a threshold of 0 and make every die look perfect.
+fn number_option<T: FromStr + Copy, F: Fn( T ) -> bool>( ( value, source ): ( Option<String>, String ), default: T, valid: F, expected: &str ) -> Result<T, String> {
+    match value {
+        None => Ok( default ),
+        Some( text ) => match text.trim().parse::<T>() {
+            Ok( v ) if valid( v ) => Ok( v ),
+            _ => Err( format!( "invalid value {:?} for {}: expected {}", text, source, expected ) ),
+        },
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,118 @@
+use std::cmp;
+use std::io::Write;
+use std::path::Path;
+
+use image::error::ImageResult;
+use image::other::BadType;
+
+use super::dimensions::Dimensions;
+use super::file::write_file;
+
+/// A group of bad pixels that touch, by a side or a corner
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub struct Cluster {
+    /// number of pixels
+    pub size   : usize,
+    /// bounding box, rows and columns start at 0 and the bounds are inclusive
+    pub top    : usize,
+    pub left   : usize,
+    pub bottom : usize,
+    pub right  : usize,
+}
+
+/// Finds the clusters of the pixels of class `bad_type`, in the order of their first pixel row by row.
+pub fn find_clusters( dims: &Dimensions, classes: &Vec<BadType>, bad_type: BadType ) -> Vec<Cluster> {
+    let mut seen = vec![ false; classes.len() ];
+    let mut clusters = Vec::new();
+    // pixels of the current cluster whose neighbours are still to be looked at
+    let mut pending: Vec<usize> = Vec::new();
+    for start in 0..classes.len() {
+        if seen[ start ] || classes[ start ] != bad_type {
+            continue;
+        }
+        seen[ start ] = true;
+        pending.push( start );
+        let mut cluster = Cluster { size: 0, top: start / dims.width, left: start % dims.width, bottom: 0, right: 0 };
+        while let Some( i ) = pending.pop() {
+            let ( row, col ) = ( i / dims.width, i % dims.width );
+            cluster.size += 1;
+            cluster.top = cmp::min( cluster.top, row );
+            cluster.left = cmp::min( cluster.left, col );
+            cluster.bottom = cmp::max( cluster.bottom, row );
+            cluster.right = cmp::max( cluster.right, col );
+            for r in row.saturating_sub( 1 )..cmp::min( row + 2, dims.height ) {
+                for c in col.saturating_sub( 1 )..cmp::min( col + 2, dims.width ) {
+                    let j = r * dims.width + c;
+                    if !seen[ j ] && classes[ j ] == bad_type {
+                        seen[ j ] = true;
+                        pending.push( j );
+                    }
+                }
+            }
+        }
+        clusters.push( cluster );
+    }
+    clusters
+}
+
+/// Counts of the clusters of a test, what is kept of them once the die is done
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct ClusterSummary {
+    pub number_of_clusters : usize,
+    /// size of the largest cluster, 0 when there is none
+    pub largest_cluster    : usize,
+    /// number of clusters of each size class, the class k holds the sizes 2^k to 2^(k+1) - 1
+    pub size_histogram     : Vec<usize>,
+}
+
+impl ClusterSummary {
+    pub fn new( clusters: &Vec<Cluster> ) -> ClusterSummary {
+        let mut size_histogram = Vec::new();
+        for cluster in clusters.iter() {
+            let class = size_class( cluster.size );
+            if size_histogram.len() <= class {
+                size_histogram.resize( class + 1, 0 );
+            }
+            size_histogram[ class ] += 1;
+        }
+        ClusterSummary {
+            number_of_clusters : clusters.len(),
+            largest_cluster    : clusters.iter().map( | c | c.size ).max().unwrap_or( 0 ),
+            size_histogram     : size_histogram,
+        }
+    }
+
+    /// The size classes that hold clusters, as `<sizes>:<clusters>` separated by spaces, e.g. `1:120 2-3:10 16-31:1`
+    pub fn histogram_text( &self ) -> String {
+        self.size_histogram.iter().enumerate().filter( | &( _, count ) | *count > 0 ).map( | ( class, count ) | {
+            let ( low, high ) = ( 1usize << class, ( 1usize << ( class + 1 ) ) - 1 );
+            if low == high {
+                format!( "{}:{}", low, count )
+            } else {
+                format!( "{}-{}:{}", low, high, count )
+            }
+        } ).collect::<Vec<String>>().join( " " )
+    }
+}
+
+// the k of 2^k <= size < 2^(k+1)
+fn size_class( size: usize ) -> usize {
+    let mut class = 0;
+    while size >> ( class + 1 ) > 0 {
+        class += 1;
+    }
+    class
+}
+
+/// Writes the bounding box of every cluster of both tests as csv, one cluster per line.
+pub fn write_clusters( output_path: &Path, open: &Vec<Cluster>, short: &Vec<Cluster> ) -> ImageResult<()> {
+    write_file( output_path, | w | {
+        try!( writeln!( w, "test,size,top,left,bottom,right" ) );
+        for &( test, clusters ) in [ ( "open", open ), ( "short", short ) ].iter() {
+            for c in clusters.iter() {
+                try!( writeln!( w, "{},{},{},{},{},{}", test, c.size, c.top, c.left, c.bottom, c.right ) );
+            }
+        }
+        Ok( () )
+    } )
+}
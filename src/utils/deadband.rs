@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use image::error::{
+    ImageError,
+    ImageResult
+};
+
+use super::dimensions::Dimensions;
+
+use utils::file::{
+    read_idp_mask,
+    spec_lines
+};
+
+/// The dead band used when nothing else is given on the command line:
+/// the diagonal band of 232 pixels starting where `row + col == height`.
+pub const DEFAULT_DEAD_BAND: &'static str = "anti_diagonal 0 231";
+
+/// One piece of the dead band geometry.
+/// Rows and columns are zero based, bounds are inclusive.
+#[derive(Debug, Clone)]
+pub enum DeadBandRegion {
+    /// pixels with `start <= row + col - height <= end`
+    AntiDiagonal { start: i64, end: i64 },
+    /// pixels with `start <= col - row <= end`
+    Diagonal { start: i64, end: i64 },
+    /// pixels with `top <= row <= bottom` and `left <= col <= right`
+    Rectangle { top: usize, left: usize, bottom: usize, right: usize },
+    /// pixels inside the polygon with the given ( col, row ) vertices
+    Polygon( Vec<( f64, f64 )> ),
+    /// pixels that are not zero in an IDP image of the same size as the test images
+    Mask { dims: Dimensions, dead: Vec<bool> },
+}
+
+impl DeadBandRegion {
+    fn contains( &self, row: usize, col: usize, dims: &Dimensions ) -> bool {
+        match *self {
+            DeadBandRegion::AntiDiagonal { start, end } => {
+                let d = ( row + col ) as i64 - dims.height as i64;
+                d >= start && d <= end
+            },
+            DeadBandRegion::Diagonal { start, end } => {
+                let d = col as i64 - row as i64;
+                d >= start && d <= end
+            },
+            DeadBandRegion::Rectangle { top, left, bottom, right } => {
+                row >= top && row <= bottom && col >= left && col <= right
+            },
+            DeadBandRegion::Polygon( ref vertices ) => point_in_polygon( col as f64, row as f64, vertices ),
+            DeadBandRegion::Mask { ref dead, .. } => dead[ row * dims.width + col ],
+        }
+    }
+
+    fn parse( line: &str ) -> ImageResult<DeadBandRegion> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let region = match ( words[0], words.len() ) {
+            ( "anti_diagonal", 3 ) => DeadBandRegion::AntiDiagonal {
+                start : try!( parse_number( words[1], line ) ),
+                end   : try!( parse_number( words[2], line ) ),
+            },
+            ( "diagonal", 3 ) => DeadBandRegion::Diagonal {
+                start : try!( parse_number( words[1], line ) ),
+                end   : try!( parse_number( words[2], line ) ),
+            },
+            ( "rectangle", 5 ) => DeadBandRegion::Rectangle {
+                top    : try!( parse_number( words[1], line ) ),
+                left   : try!( parse_number( words[2], line ) ),
+                bottom : try!( parse_number( words[3], line ) ),
+                right  : try!( parse_number( words[4], line ) ),
+            },
+            ( "polygon", n ) if n >= 4 => {
+                let mut vertices = Vec::with_capacity( n - 1 );
+                for vertex in words[1..].iter() {
+                    let xy: Vec<&str> = vertex.split( ',' ).collect();
+                    if xy.len() != 2 {
+                        return Err( dead_band_error( line, "polygon vertices are written as col,row" ) );
+                    }
+                    vertices.push( ( try!( parse_number( xy[0], line ) ), try!( parse_number( xy[1], line ) ) ) );
+                }
+                DeadBandRegion::Polygon( vertices )
+            },
+            ( "mask", 2 ) => {
+                let ( dims, dead ) = try!( read_idp_mask( Path::new( words[1] ) ) );
+                DeadBandRegion::Mask { dims: dims, dead: dead }
+            },
+            _ => return Err( dead_band_error( line, "expected one of anti_diagonal <start> <end>, diagonal <start> <end>, \
+                                                    rectangle <top> <left> <bottom> <right>, polygon <col,row> <col,row> <col,row> ... \
+                                                    or mask <idp file>" ) ),
+        };
+        Ok( region )
+    }
+}
+
+/// The dead band of a detector, as a union of regions.
+#[derive(Debug, Clone)]
+pub struct DeadBand {
+    pub regions: Vec<DeadBandRegion>,
+}
+
+impl DeadBand {
+    /// Parses a dead band description, one region per line of `spec_lines`.
+    /// An empty description means there is no dead band.
+    pub fn from_spec( spec: &str ) -> ImageResult<DeadBand> {
+        let mut regions = Vec::new();
+        for line in try!( spec_lines( spec ) ).iter() {
+            regions.push( try!( DeadBandRegion::parse( line ) ) );
+        }
+        Ok( DeadBand { regions: regions } )
+    }
+
+    /// Makes sure the regions can be applied to images of size `dims`.
+    pub fn check_dimensions( &self, dims: &Dimensions ) -> ImageResult<()> {
+        for region in self.regions.iter() {
+            if let DeadBandRegion::Mask { dims: ref mask_dims, .. } = *region {
+                if mask_dims != dims {
+                    return Err( ImageError::DimensionMismatch(
+                        format!( "the dead band mask is {}x{} but the image is {}x{}",
+                                 mask_dims.width, mask_dims.height, dims.width, dims.height )
+                    ) );
+                }
+            }
+        }
+        Ok( () )
+    }
+
+    /// Returns true if the pixel at ( row, col ) is in any of the regions.
+    pub fn contains( &self, row: usize, col: usize, dims: &Dimensions ) -> bool {
+        self.regions.iter().any( | region | region.contains( row, col, dims ) )
+    }
+}
+
+impl Default for DeadBand {
+    fn default() -> DeadBand {
+        DeadBand::from_spec( DEFAULT_DEAD_BAND ).unwrap()
+    }
+}
+
+fn parse_number<T: ::std::str::FromStr>( word: &str, line: &str ) -> ImageResult<T> {
+    word.trim().parse::<T>().map_err( | _ | dead_band_error( line, &format!( "{:?} is not a valid number", word ) ) )
+}
+
+fn dead_band_error( line: &str, msg: &str ) -> ImageError {
+    ImageError::FormatError( format!( "invalid dead band region {:?}: {}", line, msg ) )
+}
+
+// even-odd rule, see https://wrf.ecse.rpi.edu/Research/Short_Notes/pnpoly.html
+fn point_in_polygon( x: f64, y: f64, vertices: &Vec<( f64, f64 )> ) -> bool {
+    let mut inside = false;
+    let mut j = vertices.len() - 1;
+    for i in 0..vertices.len() {
+        let ( xi, yi ) = vertices[i];
+        let ( xj, yj ) = vertices[j];
+        if ( ( yi > y ) != ( yj > y ) ) && ( x < ( xj - xi ) * ( y - yi ) / ( yj - yi ) + xi ) {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
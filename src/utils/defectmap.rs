@@ -0,0 +1,61 @@
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use image::error::ImageResult;
+
+use image::other::{
+    BadType,
+    DieResult,
+    EncodingBuffer
+};
+
+use super::dimensions::Dimensions;
+
+use utils::file::write_idp;
+
+/// Colour of each pixel classification in the defect maps
+pub fn defect_colour( bad_type: BadType ) -> [u8; 3] {
+    match bad_type {
+        BadType::Unknown     => [   0,   0,   0 ], // measured and good
+        BadType::DeadBand    => [  64,  64,  64 ],
+        BadType::Ignored     => [ 128, 128, 128 ],
+        BadType::OpenBad     => [ 255,   0,   0 ],
+        BadType::OpenBadRow  => [ 255, 128,   0 ],
+        BadType::OpenBadCol  => [ 255, 255,   0 ],
+        BadType::OpenBadBoth => [ 255,   0, 255 ],
+        BadType::ShortBad    => [   0, 255, 255 ],
+    }
+}
+
+/// Writes the classification of every pixel as a colour coded binary PPM image.
+pub fn write_defect_map( output_path: &Path, dims: &Dimensions, defects: &Vec<BadType> ) -> ImageResult<()> {
+    write_ppm( output_path, dims, defects ).map_err( | e | e.for_file( output_path ) )
+}
+
+fn write_ppm( output_path: &Path, dims: &Dimensions, defects: &Vec<BadType> ) -> ImageResult<()> {
+    let f = try!( File::create( output_path ) );
+    let mut w = BufWriter::new( f );
+    try!( write!( w, "P6\n{} {}\n255\n", dims.width, dims.height ) );
+    for bad_type in defects.iter() {
+        try!( w.write_all( &defect_colour( *bad_type ) ) );
+    }
+    try!( w.flush() );
+    Ok( () )
+}
+
+/// Writes the defect map and the open and short differences of a die into `output_dir`,
+/// as `<name>_defects.ppm`, `<name>_open_diff.idp` and `<name>_short_diff.idp`.
+/// The short difference is only written when the short test was run.
+pub fn write_die_images( output_dir: &Path, name: &str, result: &DieResult ) -> ImageResult<()> {
+    try!( fs::create_dir_all( output_dir ) );
+    try!( write_defect_map( &output_dir.join( format!( "{}_defects.ppm", name ) ), &result.dims, &result.defects ) );
+
+    try!( write_idp( &output_dir.join( format!( "{}_open_diff.idp", name ) ), &result.dims, EncodingBuffer::F32( &result.open_diff ) ) );
+
+    if !result.short_diff.is_empty() {
+        try!( write_idp( &output_dir.join( format!( "{}_short_diff.idp", name ) ), &result.dims, EncodingBuffer::F32( &result.short_diff ) ) );
+    }
+    Ok( () )
+}
@@ -1,2 +1,20 @@
-pub const WIDTH: usize = 1864usize;
-pub const HEIGHT: usize = 1632usize;
\ No newline at end of file
+/// Width and height of a frame, as decoded from the IDP header.
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub struct Dimensions {
+    pub width  : usize,
+    pub height : usize,
+}
+
+impl Dimensions {
+    pub fn new( width: usize, height: usize ) -> Dimensions {
+        Dimensions {
+            width  : width,
+            height : height,
+        }
+    }
+
+    /// Total number of pixels in a frame of these dimensions
+    pub fn num_pixels( &self ) -> usize {
+        self.width * self.height
+    }
+}
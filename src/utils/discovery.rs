@@ -0,0 +1,185 @@
+use std::fs;
+use std::io;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use regex;
+use regex::Regex;
+
+use utils::file::is_capture;
+
+/// The name of a die directory when nothing else is given: `x<x>y<y>`, e.g. `x12y5`
+pub const DEFAULT_DIE_PATTERN: &'static str = r"^[Xx](?P<x>\d+)[Yy](?P<y>\d+)$";
+
+// deep enough for lot/wafer/date/die archives, and a bound on directory links that loop
+const MAX_DEPTH: usize = 32;
+
+/// A pattern on the path of a directory relative to the test directory, with `/` between the directories.
+/// `*` is any part of a name, `?` any one character and `**` any number of directories.
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pub pattern : String,
+    regex       : Regex,
+}
+
+impl Glob {
+    pub fn new( pattern: &str ) -> Result<Glob, String> {
+        let mut re = String::from( "^" );
+        let chars = pattern.chars().collect::<Vec<char>>();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '*' if chars.get( i + 1 ) == Some( &'*' ) => {
+                    if chars.get( i + 2 ) == Some( &'/' ) {
+                        re.push_str( "(?:.*/)?" );
+                        i += 1;
+                    } else {
+                        re.push_str( ".*" );
+                    }
+                    i += 1;
+                },
+                '*' => re.push_str( "[^/]*" ),
+                '?' => re.push_str( "[^/]" ),
+                c   => re.push_str( &regex::quote( &c.to_string() ) ),
+            }
+            i += 1;
+        }
+        re.push( '$' );
+        match Regex::new( &re ) {
+            Ok( regex ) => Ok( Glob { pattern: pattern.to_string(), regex: regex } ),
+            Err( e ) => Err( format!( "invalid pattern {:?}: {}", pattern, e ) ),
+        }
+    }
+
+    pub fn matches( &self, relative_path: &str ) -> bool {
+        self.regex.is_match( relative_path )
+    }
+}
+
+/// Where the dies are looked for under the test directory, and how their x and y are read from the directory names
+#[derive(Debug, Clone)]
+pub struct Discovery {
+    /// only the directories that match one of them are dies, every one when empty
+    pub include      : Vec<Glob>,
+    /// the directories that match one of them, and everything below them, are left out
+    pub exclude      : Vec<Glob>,
+    /// regexes with the named groups `x` and `y`, tried in order on the name of a die directory
+    pub die_patterns : Vec<Regex>,
+}
+
+impl Default for Discovery {
+    fn default() -> Discovery {
+        Discovery {
+            include      : Vec::new(),
+            exclude      : Vec::new(),
+            die_patterns : vec![ Regex::new( DEFAULT_DIE_PATTERN ).unwrap() ],
+        }
+    }
+}
+
+impl Discovery {
+    /// Parses the globs and the die patterns, each separated by `;`. An empty text keeps the default.
+    pub fn from_specs( include: &str, exclude: &str, die_patterns: &str ) -> Result<Discovery, String> {
+        let mut discovery = Discovery::default();
+        discovery.include = try!( split_spec( include ).into_iter().map( Glob::new ).collect::<Result<Vec<Glob>, String>>() );
+        discovery.exclude = try!( split_spec( exclude ).into_iter().map( Glob::new ).collect::<Result<Vec<Glob>, String>>() );
+        let patterns = split_spec( die_patterns );
+        if !patterns.is_empty() {
+            let mut regexes = Vec::with_capacity( patterns.len() );
+            for pattern in patterns.into_iter() {
+                if !pattern.contains( "(?P<x>" ) || !pattern.contains( "(?P<y>" ) {
+                    return Err( format!( "invalid die pattern {:?}: it needs the groups (?P<x>...) and (?P<y>...)", pattern ) );
+                }
+                match Regex::new( pattern ) {
+                    Ok( regex ) => regexes.push( regex ),
+                    Err( e ) => return Err( format!( "invalid die pattern {:?}: {}", pattern, e ) ),
+                }
+            }
+            discovery.die_patterns = regexes;
+        }
+        Ok( discovery )
+    }
+
+    /// The x and y of a die directory name, from the first die pattern that matches it
+    pub fn die_position( &self, name: &str ) -> Option<( u32, u32 )> {
+        self.die_patterns.iter().filter_map( | re | re.captures( name ) ).filter_map( | caps | {
+            match ( caps.name( "x" ).and_then( | x | x.parse::<u32>().ok() ), caps.name( "y" ).and_then( | y | y.parse::<u32>().ok() ) ) {
+                ( Some( x ), Some( y ) ) => Some( ( x, y ) ),
+                _ => None,
+            }
+        } ).next()
+    }
+}
+
+fn split_spec( spec: &str ) -> Vec<&str> {
+    spec.split( ';' ).map( | s | s.trim() ).filter( | s | !s.is_empty() ).collect()
+}
+
+/// A directory with the captures of one die
+#[derive(Debug, Clone)]
+pub struct DieDir {
+    pub x     : u32,
+    pub y     : u32,
+    pub dir   : PathBuf,
+    /// the reset and signal captures in the directory
+    pub paths : Vec<PathBuf>,
+}
+
+/// Finds every directory under `root`, at any depth, that holds captures and passes the include and exclude globs.
+/// A directory whose name matches no die pattern, or that can not be read, is skipped with a warning on stderr.
+pub fn find_dies( root: &Path, discovery: &Discovery ) -> io::Result<Vec<DieDir>> {
+    match fs::metadata( root ) {
+        Ok( ref md ) if md.is_dir() => {},
+        Ok( _ ) => return Err( Error::new( ErrorKind::Other, format!( "The given test_dir {:?} is not a directory", root ) ) ),
+        Err( _ ) => {
+            let _ = writeln!( io::stderr(), " {:?} could not be found.  Please double check the value given to the -t parameter", root );
+            return Err( Error::new( ErrorKind::Other, format!( "The given test_dir {:?} is not a directory or is not accessible", root ) ) );
+        },
+    }
+    let mut dies = Vec::new();
+    visit( root, "", 0, discovery, &mut dies );
+    Ok( dies )
+}
+
+fn visit( dir: &Path, relative: &str, depth: usize, discovery: &Discovery, dies: &mut Vec<DieDir> ) {
+    let mut entries = match fs::read_dir( dir ).and_then( | entries | entries.map( | e | e.map( | e | e.path() ) ).collect::<io::Result<Vec<PathBuf>>>() ) {
+        Ok( entries ) => entries,
+        Err( e ) => {
+            warn( &format!( "skipping {}: {}", dir.display(), e ) );
+            return;
+        },
+    };
+    entries.sort();
+
+    let ( subdirs, files ): ( Vec<PathBuf>, Vec<PathBuf> ) = entries.into_iter().partition( | path | path.is_dir() );
+    let captures = files.into_iter().filter( | path | is_capture( path ) ).collect::<Vec<PathBuf>>();
+    // the test directory itself is not a die
+    if !captures.is_empty() && depth > 0 && is_included( relative, discovery ) {
+        let name = dir.file_name().map( | n | n.to_string_lossy().into_owned() ).unwrap_or( String::new() );
+        match discovery.die_position( &name ) {
+            Some( ( x, y ) ) => dies.push( DieDir { x: x, y: y, dir: dir.to_path_buf(), paths: captures } ),
+            None => warn( &format!( "skipping {}: its name does not match any die pattern", dir.display() ) ),
+        }
+    }
+
+    for subdir in subdirs.iter() {
+        let name = subdir.file_name().map( | n | n.to_string_lossy().into_owned() ).unwrap_or( String::new() );
+        let sub_relative = if relative.is_empty() { name } else { format!( "{}/{}", relative, name ) };
+        if discovery.exclude.iter().any( | glob | glob.matches( &sub_relative ) ) {
+            continue;
+        }
+        if depth + 1 >= MAX_DEPTH {
+            warn( &format!( "skipping {}: it is more than {} directories deep", subdir.display(), MAX_DEPTH ) );
+            continue;
+        }
+        visit( subdir, &sub_relative, depth + 1, discovery, dies );
+    }
+}
+
+fn is_included( relative: &str, discovery: &Discovery ) -> bool {
+    discovery.include.is_empty() || discovery.include.iter().any( | glob | glob.matches( relative ) )
+}
+
+fn warn( msg: &str ) {
+    let _ = writeln!( io::stderr(), "warning: {}", msg );
+}
@@ -1,20 +1,18 @@
-use std::io;
-use std::io::{BufReader, BufWriter, Error, ErrorKind};
+use std::fmt;
+use std::str::FromStr;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::fs;
-use std::fs::{File,DirEntry};
+use std::fs::File;
 
 use std::path::{Path,PathBuf};
 
-use regex::Regex;
-
 use stream::{
     ByteOrder,
-    EndianWriter,
-    SmartWriter,
     SmartReader
 };
 
 use image::error::{
+    ImageError,
     ImageResult
 };
 
@@ -24,90 +22,228 @@ use decoder::{
     ImageDecoder
 };
 
+use encoder::IDPEncoder;
+
 use image::other::{
     DecodingResult,
-    BadType,
-    Pixel
+    EncodingBuffer
 };
 
-use utils::pixelops::{
-    make_pixel_u16,
-    make_pixel_f32
+use utils::dimensions::Dimensions;
+
+use utils::frames::{
+    FrameCombine,
+    FrameCombiner
 };
 
+use utils::pairing::{ bias_code, parse_bias_code };
+
 #[allow(non_snake_case)]
-pub fn absolute_difference_of_IDP_Imges( lhs: &PathBuf, rhs: &PathBuf ) -> Option<Vec<Pixel> >{
-    let lhs_pixels = read_test_idp( lhs ).unwrap();
-    let rhs_pixels = read_test_idp( rhs ).unwrap();
-    // make sure they are of the same dimensions etc..
-    let pairs = lhs_pixels.iter().zip( rhs_pixels.iter());
-    let diffs:Vec<Pixel> = pairs.map( | (left , right ) | {
-        match left.valid {
-            BadType::DeadBand => Pixel{ value : 999f32, valid: BadType::DeadBand  },
-            _ => Pixel{ value : { let x = left.value - right.value; x.abs() }, valid: BadType::Unknown  }
+pub fn absolute_difference_of_IDP_Imges( lhs: &TestImage, rhs: &TestImage, frame_combine: FrameCombine ) -> ImageResult<( Dimensions, Vec<f32> )> {
+    let ( lhs_dims, mut diffs ) = try!( read_test_image( lhs, frame_combine ) );
+    let ( rhs_dims, rhs_values ) = try!( read_test_image( rhs, frame_combine ) );
+    if lhs_dims != rhs_dims {
+        return Err( ImageError::DimensionMismatch(
+            format!( "{} is {}x{} but {} is {}x{}",
+                     lhs, lhs_dims.width, lhs_dims.height,
+                     rhs, rhs_dims.width, rhs_dims.height )
+        ) );
+    }
+    // the difference is taken in place, in the values of the left image
+    for ( left, right ) in diffs.iter_mut().zip( rhs_values.iter() ) {
+        *left = ( *left - *right ).abs();
+    }
+    Ok( ( lhs_dims, diffs ) )
+}
+
+/// One image of an open or short pair
+#[derive(Copy, Debug, Clone)]
+pub enum TestImage<'a> {
+    /// the values of a single capture
+    Capture( &'a PathBuf ),
+    /// correlated double sampling: the signal capture minus the reset capture
+    Cds { signal: &'a PathBuf, reset: &'a PathBuf },
+}
+
+impl<'a> fmt::Display for TestImage<'a> {
+    fn fmt( &self, fmt: &mut fmt::Formatter ) -> Result<(), fmt::Error> {
+        match *self {
+            TestImage::Capture( path ) => write!( fmt, "{}", path.display() ),
+            TestImage::Cds { signal, reset } => write!( fmt, "{} - {}", signal.display(), reset.display() ),
         }
-    }).collect();
-    Some( diffs )
+    }
 }
 
-// remove this once tests are added
-#[allow(dead_code)] 
-fn make_test_idp( input_path: &Path) {
-    println!("This should create a test IDP file!");
-    let f = match File::create( input_path ) {
-        Ok( file ) => file,
-        Err( msg ) => { println!("{}", msg); panic!( format!("Panic! unable to create file at : {:?}", input_path ) ); }
+fn read_test_image( image: &TestImage, frame_combine: FrameCombine ) -> ImageResult<( Dimensions, Vec<f32> )> {
+    match *image {
+        TestImage::Capture( path ) => read_test_idp( path, frame_combine ),
+        TestImage::Cds { signal, reset } => {
+            let ( signal_dims, mut cds ) = try!( read_test_idp( signal, frame_combine ) );
+            let ( reset_dims, reset_values ) = try!( read_test_idp( reset, frame_combine ) );
+            if signal_dims != reset_dims {
+                return Err( ImageError::DimensionMismatch(
+                    format!( "{} is {}x{} but {} is {}x{}",
+                             signal.display(), signal_dims.width, signal_dims.height,
+                             reset.display(), reset_dims.width, reset_dims.height )
+                ) );
+            }
+            for ( s, r ) in cds.iter_mut().zip( reset_values.iter() ) {
+                *s = *s - *r;
+            }
+            Ok( ( signal_dims, cds ) )
+        },
+    }
+}
+
+/// The lines of a description given to an option such as -d, -p or --spec.
+/// `spec` is either the path of a file with one line per item, or the items themselves separated by `;`.
+/// Everything after a `#` is a comment; the lines are trimmed and the empty ones left out.
+pub fn spec_lines( spec: &str ) -> ImageResult<Vec<String>> {
+    let text = if Path::new( spec ).is_file() {
+        let mut text = String::new();
+        let mut f = try!( File::open( spec ) );
+        try!( f.read_to_string( &mut text ) );
+        text
+    } else {
+        spec.replace( ";", "\n" )
     };
+    Ok( text.lines()
+            .map( | line | line.split( '#' ).next().unwrap_or( "" ).trim().to_string() )
+            .filter( | line | !line.is_empty() )
+            .collect() )
+}
+
+/// Reads only the header of the IDP file at `input_path` and returns its dimensions.
+pub fn read_idp_dimensions( input_path: &Path ) -> ImageResult<Dimensions> {
+    read_idp_header( input_path ).map_err( | e | e.for_file( input_path ) )
+}
+
+fn read_idp_header( input_path: &Path ) -> ImageResult<Dimensions> {
+    let f = try!( File::open( input_path ) );
+    let bufr = BufReader::new( &f );
+    let rdr = SmartReader::wrap( bufr, ByteOrder::LittleEndian );
+    let mut idp_decoder = try!( IDPDecoder::new( rdr ) );
+    let ( width, height ) = try!( idp_decoder.dimensions() );
+    Ok( Dimensions::new( width as usize, height as usize ) )
+}
 
-    let w = BufWriter::new( &f );
-    let mut wtr = SmartWriter::wrap(w, ByteOrder::LittleEndian);
-
-    let fmt1 = 0u32;
-    let fmt2 = 2u32;
-      
-    let number_of_columns :u32 = 1864u32;
-    let number_of_rows: u32    = 1632u32;
-    
-    wtr.write_u32( fmt1 ).unwrap();
-    wtr.write_u32( fmt2 ).unwrap();
-    wtr.write_u32( number_of_columns ).unwrap();
-    wtr.write_u32( number_of_rows    ).unwrap();
-    for row in (0..number_of_rows ) {
-        for col in ( 0..number_of_columns ) {
-            wtr.write_f32( ( col * 100 + row ) as f32  ).unwrap()
+/// Counts the frames of the IDP file at `input_path` from their headers, without reading the pixels.
+pub fn read_idp_frame_count( input_path: &Path ) -> ImageResult<usize> {
+    read_frame_count( input_path ).map_err( | e | e.for_file( input_path ) )
+}
+
+fn read_frame_count( input_path: &Path ) -> ImageResult<usize> {
+    let f = try!( File::open( input_path ) );
+    let bufr = BufReader::new( &f );
+    let rdr = SmartReader::wrap( bufr, ByteOrder::LittleEndian );
+    let mut idp_decoder = try!( IDPDecoder::new( rdr ) );
+    idp_decoder.frame_count()
+}
+
+/// Checks that every file of a die has the same dimensions and returns them.
+pub fn common_dimensions( file_set: &Vec<PathBuf> ) -> ImageResult<Dimensions> {
+    let mut common: Option<( &PathBuf, Dimensions )> = None;
+    for path in file_set.iter() {
+        let dims = try!( read_idp_dimensions( path ) );
+        match common {
+            None => common = Some( ( path, dims ) ),
+            Some( ( ref first_path, first_dims ) ) => {
+                if first_dims != dims {
+                    return Err( ImageError::DimensionMismatch(
+                        format!( "{} is {}x{} but {} is {}x{}",
+                                 path.display(), dims.width, dims.height,
+                                 first_path.display(), first_dims.width, first_dims.height )
+                    ) );
+                }
+            }
         }
     }
+    match common {
+        Some( ( _, dims ) ) => Ok( dims ),
+        None => Err( ImageError::FormatError( "no IDP files found in the set".to_string() ) ),
+    }
 }
 
+/// Writes `buffer` to a new IDP file at `output_path`.
+pub fn write_idp( output_path: &Path, dims: &Dimensions, buffer: EncodingBuffer ) -> ImageResult<()> {
+    write_idp_file( output_path, dims, buffer ).map_err( | e | e.for_file( output_path ) )
+}
 
-fn read_test_idp( input_path: &Path) -> ImageResult<Vec<Pixel> > {
-    let f = match File::open( input_path ) {
-        Ok( file ) => file,
-        Err( msg ) => { println!("{}", msg); panic!( "could not open input file" ); }
-    };
+fn write_idp_file( output_path: &Path, dims: &Dimensions, buffer: EncodingBuffer ) -> ImageResult<()> {
+    let f = try!( File::create( output_path ) );
+    let w = BufWriter::new( f );
+    let mut idp_encoder = IDPEncoder::new( w );
+    idp_encoder.write_image( dims.width as u32, dims.height as u32, buffer )
+}
+
+/// Writes a text file such as a csv at `output_path` with `write`, creating its directory if needed.
+pub fn write_file<F>( output_path: &Path, write: F ) -> ImageResult<()> where F: FnOnce( &mut BufWriter<File> ) -> ImageResult<()> {
+    create_and_write( output_path, write ).map_err( | e | e.for_file( output_path ) )
+}
+
+fn create_and_write<F>( output_path: &Path, write: F ) -> ImageResult<()> where F: FnOnce( &mut BufWriter<File> ) -> ImageResult<()> {
+    if let Some( dir ) = output_path.parent() {
+        try!( fs::create_dir_all( dir ) );
+    }
+    let f = try!( File::create( output_path ) );
+    let mut w = BufWriter::new( f );
+    try!( write( &mut w ) );
+    try!( w.flush() );
+    Ok( () )
+}
+
+fn read_test_idp( input_path: &Path, frame_combine: FrameCombine ) -> ImageResult<( Dimensions, Vec<f32> )> {
+    read_idp_values( input_path, frame_combine ).map_err( | e | e.for_file( input_path ) )
+}
+
+fn read_idp_values( input_path: &Path, frame_combine: FrameCombine ) -> ImageResult<( Dimensions, Vec<f32> )> {
+    let f = try!( File::open( input_path ) );
 
     let bufr = BufReader::new( &f );
     let rdr = SmartReader::wrap( bufr, ByteOrder::LittleEndian );
-    let mut idp_decoder = IDPDecoder::new( rdr ).unwrap();
-    let decoding_result = idp_decoder.read_image().unwrap();
-
-    let pixels: Vec<Pixel> = match decoding_result {
-       DecodingResult::U16( ref _buffer) =>
-       {
-           let vs: &Vec<u16> = _buffer;// Make a U16 Image buffer
-           let ps: Vec<Pixel> = vs.iter().enumerate().map( | (i, val) | make_pixel_u16( ( i, val ) ) ).collect();
-           ps
-       },
-       DecodingResult::F32( ref _buffer) =>
-       {
-           let vs: &Vec<f32> = _buffer;// make a F32 Image buffer
-           
-           let ps: Vec<Pixel> = vs.iter().enumerate().map( | (i,val) | make_pixel_f32( ( i, val ) ) ).collect();
-           ps
-       },
-   }; 
-
-    Ok( pixels )
+    let mut idp_decoder = try!( IDPDecoder::new( rdr ) );
+    let ( width, height ) = try!( idp_decoder.dimensions() );
+    let dims = Dimensions::new( width as usize, height as usize );
+
+    // a file can hold several frames, one after the other, each with its own header
+    let mut frames = FrameCombiner::new( frame_combine );
+    loop {
+        let ( frame_width, frame_height ) = try!( idp_decoder.dimensions() );
+        if ( frame_width as usize, frame_height as usize ) != ( dims.width, dims.height ) {
+            return Err( ImageError::DimensionMismatch(
+                format!( "frame {} is {}x{} but frame 0 is {}x{}", frames.len(), frame_width, frame_height, dims.width, dims.height )
+            ) );
+        }
+        let values: Vec<f32> = match try!( idp_decoder.read_image() ) {
+           DecodingResult::U16( ref buffer ) => buffer.iter().map( | val | *val as f32 ).collect(),
+           DecodingResult::F32( buffer )     => buffer,
+        };
+        frames.add( values );
+        if !try!( idp_decoder.more_images() ) {
+            break;
+        }
+        idp_decoder = try!( idp_decoder.next_image() );
+    }
+    Ok( ( dims, frames.finish() ) )
+}
+
+/// Reads an IDP image used as a mask, every pixel that is not zero is set.
+pub fn read_idp_mask( input_path: &Path ) -> ImageResult<( Dimensions, Vec<bool> )> {
+    read_idp_mask_values( input_path ).map_err( | e | e.for_file( input_path ) )
+}
+
+fn read_idp_mask_values( input_path: &Path ) -> ImageResult<( Dimensions, Vec<bool> )> {
+    let f = try!( File::open( input_path ) );
+    let bufr = BufReader::new( &f );
+    let rdr = SmartReader::wrap( bufr, ByteOrder::LittleEndian );
+    let mut idp_decoder = try!( IDPDecoder::new( rdr ) );
+    let ( width, height ) = try!( idp_decoder.dimensions() );
+    let dims = Dimensions::new( width as usize, height as usize );
+    let mask = match try!( idp_decoder.read_image() ) {
+        DecodingResult::U16( ref buffer ) => buffer.iter().map( | v | *v != 0 ).collect(),
+        DecodingResult::F32( ref buffer ) => buffer.iter().map( | v | *v != 0.0 ).collect(),
+    };
+    Ok( ( dims, mask ) )
 }
 
 // // ENH: instead of a vector of four,
@@ -117,81 +253,240 @@ fn read_test_idp( input_path: &Path) -> ImageResult<Vec<Pixel> > {
 // }
 
 
-// reduced to exactly two levels of dir. 
-// user specifies top level, each child dir of this has all the .idp files 
-// we pick only the four with PNReset in their name.
-// 
-
-fn process_tail_dirs<F>(dir: &Path, cb: &mut F) -> io::Result<()> where F: FnMut(Vec<DirEntry>) {
-    if fs::metadata( dir ).unwrap().is_dir() {
-        // println!("Selecting Reset out files from {} \n", dir.display());
-        // let xy = TODO: see if it makes sense to extract x and y here
-
-        let entries = try!(fs::read_dir(dir)); // Result<ReadDir>
-        // from https://doc.rust-lang.org/std/fs/struct.ReadDir.html
-        // impl Iterator for ReadDir
-        // type Item = Result<DirEntry>
-        // https://doc.rust-lang.org/std/iter/trait.Iterator.html#method.filter_map
-
-        // fn filter_map<B, F>(self, f: F) -> FilterMap<Self, F> 
-        // where F: FnMut(Self::Item) -> Option<B>
-
-        let reset_entries = entries.filter_map( | entry | { // the type of entry is Result<DirEntry>
-                 let this_entry = entry.unwrap();           // unwrapping it will get us the wrapped DirEntry. But on Err it will panic
-                 // try!(entry); // This will unwrap from Result but will exit the lambda on Err
-                 let this_entry_path = &this_entry.path();
-                 if !fs::metadata( this_entry_path ).unwrap().is_dir() && this_entry_path.to_str().unwrap().contains("PNReset") {
-                     Some (this_entry )
-                 } else {
-                         None
-                 }
-             });
-        let process_these = reset_entries.collect::< Vec< DirEntry> >();
-        cb( process_these );
-    }
-    Ok(())
-}
-
-
-// how do we handle empty dirs?
-
-// this function enables recursively walking down/ visiting a directory tree.
-// But we only need to go two levels deep.
-// based on the example at https://doc.rust-lang.org/std/fs/fn.read_dir.html
-// <FerrousOxide> so in cb : &mut F where F: FnMut(DirEntry) cb is a reference to a mutable ( closure that can change the args it captures, and takes a DirEntry as the arg ) ?
-// <mbrubeck> FerrousOxide: Yes, though to get picky about terminology, the captured variables are not "arguments" 
-// -- they are sometimes called "upvars" (since they come from a scope "above" the closure's body)
-
-pub fn walk_test_dir<F>(dir: &Path, cb: &mut F) -> io::Result<()> where F: FnMut(Vec<DirEntry>) {
-    let metadata = fs::metadata( dir );
-    let md = match metadata {
-        Ok( m ) => m,
-        Err( _e ) => { 
-            println!( " {:?} could not be found.  Please double check the value given to the -t parameter", dir ); 
-            return Err( Error::new(ErrorKind::Other, format!( "The given test_dir {:?} is not a directory or is not accessible", dir) ) )
-        }, 
-    };
-    if md.is_dir() {
-        // println!("\n Testing Reset out files from sub dirs of : {} \n", dir.display());
-
-        for entry in try!(fs::read_dir(dir)) {
-            let this_entry = try!(entry);
-            let this_entry_path = &this_entry.path();
-            if fs::metadata( this_entry_path ).unwrap().is_dir() {
-                try!(process_tail_dirs(this_entry_path, cb));
-            } 
+/// True for the reset and signal captures, the files with PNReset or PNSignal in their name
+pub fn is_capture( path: &Path ) -> bool {
+    let name = path.to_string_lossy();
+    name.contains( "PNReset" ) || name.contains( "PNSignal" )
+}
+
+/// Read out channel of a capture, `PNResetOut` or `PNSignalOut` in the file name
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub enum Channel {
+    Reset,
+    Signal,
+}
+
+impl Channel {
+    /// The channel of a file name, without checking the rest of the name
+    pub fn of( path: &Path ) -> Option<Channel> {
+        let name = match path.file_name() {
+            Some( name ) => name.to_string_lossy().into_owned(),
+            None => return None,
+        };
+        name.split( | c | c == '_' || c == '.' ).filter_map( | word | match word {
+            "PNResetOut"  => Some( Channel::Reset ),
+            "PNSignalOut" => Some( Channel::Signal ),
+            _ => None,
+        } ).next()
+    }
+
+    pub fn name( &self ) -> &'static str {
+        match *self {
+            Channel::Reset  => "PNResetOut",
+            Channel::Signal => "PNSignalOut",
+        }
+    }
+}
+
+/// Which channel the open and short pairs are built from
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub enum ChannelMode {
+    /// the `PNResetOut` captures
+    Reset,
+    /// the `PNSignalOut` captures
+    Signal,
+    /// correlated double sampling, `PNSignalOut` minus `PNResetOut` of the same bias code
+    Cds,
+}
+
+impl FromStr for ChannelMode {
+    type Err = String;
+
+    fn from_str( s: &str ) -> Result<ChannelMode, String> {
+        match s.trim() {
+            "reset"  => Ok( ChannelMode::Reset ),
+            "signal" => Ok( ChannelMode::Signal ),
+            "cds"    => Ok( ChannelMode::Cds ),
+            other    => Err( format!( "{:?} is not a channel, use reset, signal or cds", other ) ),
+        }
+    }
+}
+
+impl ChannelMode {
+    /// Returns true if the file at `path` is a C<v1><v2> capture read in this mode,
+    /// so that e.g. the commCaptureOutputBuffer files are left out
+    pub fn uses( &self, path: &Path ) -> bool {
+        if bias_code( path ).is_none() {
+            return false;
+        }
+        match ( *self, Channel::of( path ) ) {
+            ( ChannelMode::Reset,  Some( Channel::Reset  ) ) => true,
+            ( ChannelMode::Signal, Some( Channel::Signal ) ) => true,
+            ( ChannelMode::Cds,    Some( _ ) )               => true,
+            _ => false,
         }
-        Ok(())
+    }
+}
+
+/// The metadata in the name of an IDP capture, e.g.
+/// `L_W_X-32768_Y-32768_D35_C1717_PNSignalOut_VT1.7001E+0_VB1.7001E+0_O3_BDx3_T150707111948.IDP`
+#[derive(PartialEq, Debug, Clone)]
+pub struct CaptureName {
+    /// first word of the name, `L` when the lot was not filled in
+    pub lot       : String,
+    /// second word of the name, `W` when the wafer was not filled in
+    pub wafer     : String,
+    /// stage position, `X<x>` and `Y<y>`
+    pub stage_x   : i32,
+    pub stage_y   : i32,
+    /// die number, `D<n>`
+    pub die       : u32,
+    /// voltage codes of the bias code `C<v1><v2>`
+    pub v1        : String,
+    pub v2        : String,
+    pub channel   : Channel,
+    /// `VT<volts>` and `VB<volts>`, None when the name leaves them out
+    pub vt        : Option<f32>,
+    pub vb        : Option<f32>,
+    /// `O<n>` setting
+    pub o         : u32,
+    /// `BD<setting>`, e.g. `x3`
+    pub bd        : String,
+    /// `T<yymmddhhmmss>`, kept as written so that it sorts in time order
+    pub timestamp : String,
+}
+
+impl CaptureName {
+    /// Parses the file name of `path`. Every field but VT and VB must be present, in order, and well formed.
+    pub fn parse( path: &Path ) -> ImageResult<CaptureName> {
+        let name = match path.file_name() {
+            Some( name ) => name.to_string_lossy().into_owned(),
+            None => return Err( ImageError::BadCaptureName( format!( "{} has no file name", path.display() ) ) ),
+        };
+        parse_capture_name( &name ).map_err( | e | e.for_file( path ) )
+    }
+
+    /// The bias code as written in the name, e.g. `C1725`
+    pub fn bias_code( &self ) -> String {
+        format!( "C{}{}", self.v1, self.v2 )
+    }
+}
+
+fn parse_capture_name( name: &str ) -> ImageResult<CaptureName> {
+    let stem = match name.rfind( '.' ) {
+        Some( dot ) if name[ dot + 1.. ].to_lowercase() == "idp" => &name[ ..dot ],
+        _ => return Err( ImageError::BadCaptureName( "the name does not end with .IDP".to_string() ) ),
+    };
+    let words: Vec<&str> = stem.split( '_' ).collect();
+    // VT and VB are left out by some of the capture software
+    let has_volts = match words.len() {
+        12 => true,
+        10 => false,
+        n  => return Err( ImageError::BadCaptureName(
+            format!( "expected 10 or 12 fields separated by _ ( L_W_X<x>_Y<y>_D<die>_C<v1><v2>_PN<Reset|Signal>Out[_VT<v>_VB<v>]_O<n>_BD<s>_T<time> ) but found {}", n )
+        ) ),
+    };
+    // the fields are checked in order, so that the first bad one is reported
+    let stage_x: i32 = try!( capture_field( words[2], "X", "stage x" ) );
+    let stage_y: i32 = try!( capture_field( words[3], "Y", "stage y" ) );
+    let die: u32 = try!( capture_field( words[4], "D", "die number" ) );
+    let ( v1, v2 ) = match parse_bias_code( words[5] ) {
+        Some( codes ) => codes,
+        None => return Err( ImageError::BadCaptureName( format!( "{:?} is not a bias code like C1725", words[5] ) ) ),
+    };
+    let channel = match words[6] {
+        "PNResetOut"  => Channel::Reset,
+        "PNSignalOut" => Channel::Signal,
+        other => return Err( ImageError::BadCaptureName( format!( "{:?} is not a channel, expected PNResetOut or PNSignalOut", other ) ) ),
+    };
+    let ( vt, vb, rest ) = if has_volts {
+        let vt: f32 = try!( capture_field( words[7], "VT", "top voltage" ) );
+        let vb: f32 = try!( capture_field( words[8], "VB", "bottom voltage" ) );
+        ( Some( vt ), Some( vb ), &words[9..] )
     } else {
-        Err( Error::new(ErrorKind::Other, format!( "The given test_dir {:?} is not a directory", dir ) ) ) 
+        ( None, None, &words[7..] )
+    };
+    let o: u32 = try!( capture_field( rest[0], "O", "O setting" ) );
+    let bd: String = try!( capture_field( rest[1], "BD", "BD setting" ) );
+    let timestamp: String = try!( capture_field( rest[2], "T", "timestamp" ) );
+    if timestamp.len() != 12 || !timestamp.chars().all( | c | c.is_digit( 10 ) ) {
+        return Err( ImageError::BadCaptureName( format!( "{:?} is not a timestamp like T150707111948", rest[2] ) ) );
     }
+    Ok( CaptureName {
+        lot       : words[0].to_string(),
+        wafer     : words[1].to_string(),
+        stage_x   : stage_x,
+        stage_y   : stage_y,
+        die       : die,
+        v1        : v1,
+        v2        : v2,
+        channel   : channel,
+        vt        : vt,
+        vb        : vb,
+        o         : o,
+        bd        : bd,
+        timestamp : timestamp,
+    } )
 }
 
-pub fn extract_x_y_from_name<'a>( dir : &'a PathBuf ) -> ( u32, u32 ) {
-    let path = dir; // .path();
-    let xy = path.parent().unwrap().file_name().unwrap();
-    let re = Regex::new(r"^[Xx](?P<x>\d+)[Yy](?P<y>\d+)$").unwrap();
-    let caps = re.captures( xy.to_str().unwrap() ).unwrap();
-    // for cap in caps.iter() { println!( "{:?}", cap ); }
-    ( caps.at( 1 ).unwrap().trim().parse::<u32>().ok().unwrap(), caps.at( 2 ).unwrap().trim().parse::<u32>().ok().unwrap() )
+// the value of a field written as <prefix><value>
+fn capture_field<T: ::std::str::FromStr>( word: &str, prefix: &str, what: &str ) -> ImageResult<T> {
+    let bad_field = || ImageError::BadCaptureName( format!( "{:?} is not a {}, expected {}<value>", word, what, prefix ) );
+    if !word.starts_with( prefix ) || word.len() == prefix.len() {
+        return Err( bad_field() );
+    }
+    word[ prefix.len().. ].parse::<T>().map_err( | _ | bad_field() )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bad_name( name: &str ) -> bool {
+        match parse_capture_name( name ) {
+            Err( ImageError::BadCaptureName( _ ) ) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn name_with_voltages() {
+        let capture = parse_capture_name( "L_W_X-32768_Y-32768_D35_C1717_PNSignalOut_VT1.7001E+0_VB1.7001E+0_O3_BDx3_T150707111948.IDP" ).unwrap();
+        assert_eq!( capture, CaptureName {
+            lot       : "L".to_string(),
+            wafer     : "W".to_string(),
+            stage_x   : -32768,
+            stage_y   : -32768,
+            die       : 35,
+            v1        : "17".to_string(),
+            v2        : "17".to_string(),
+            channel   : Channel::Signal,
+            vt        : Some( 1.7001 ),
+            vb        : Some( 1.7001 ),
+            o         : 3,
+            bd        : "x3".to_string(),
+            timestamp : "150707111948".to_string(),
+        } );
+    }
+
+    #[test]
+    fn name_without_voltages() {
+        let capture = parse_capture_name( "LOT7_W02_X12_Y-5_D7_C1725_PNResetOut_O3_BDx3_T150707135036.idp" ).unwrap();
+        assert_eq!( ( capture.lot.as_str(), capture.wafer.as_str() ), ( "LOT7", "W02" ) );
+        assert_eq!( ( capture.stage_x, capture.stage_y, capture.die ), ( 12, -5, 7 ) );
+        assert_eq!( ( capture.v1.as_str(), capture.v2.as_str() ), ( "17", "25" ) );
+        assert_eq!( capture.channel, Channel::Reset );
+        assert_eq!( ( capture.vt, capture.vb ), ( None, None ) );
+        assert_eq!( capture.timestamp, "150707135036" );
+    }
+
+    #[test]
+    fn bad_fields() {
+        assert!( bad_name( "L_W_Xab_Y-32768_D35_C1717_PNSignalOut_O3_BDx3_T150707111948.IDP" ) );
+        assert!( bad_name( "L_W_X-32768_Y-32768_D35_C171_PNSignalOut_O3_BDx3_T150707111948.IDP" ) );
+        assert!( bad_name( "L_W_X-32768_Y-32768_D35_C1717_PNFooOut_O3_BDx3_T150707111948.IDP" ) );
+        assert!( bad_name( "L_W_X-32768_Y-32768_D35_C1717_PNSignalOut_O3_BDx3_T1507.IDP" ) );
+        assert!( bad_name( "L_W_X-32768_Y-32768_D35_C1717_PNSignalOut_O3_BDx3_T150707111948.txt" ) );
+        assert!( bad_name( "L_W_X-32768_Y-32768_D35_C1717_PNSignalOut_VT1.7_O3_BDx3_T150707111948.IDP" ) );
+    }
 }
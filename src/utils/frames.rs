@@ -0,0 +1,108 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// How the frames of a file with several frames are combined into one image
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub enum FrameCombine {
+    /// pixel by pixel mean of the frames
+    Mean,
+    /// pixel by pixel median of the frames
+    Median,
+}
+
+impl FromStr for FrameCombine {
+    type Err = String;
+
+    fn from_str( s: &str ) -> Result<FrameCombine, String> {
+        match s.trim() {
+            "mean"   => Ok( FrameCombine::Mean ),
+            "median" => Ok( FrameCombine::Median ),
+            other    => Err( format!( "{:?} is not a way to combine frames, use mean or median", other ) ),
+        }
+    }
+}
+
+/// Combines the frames of a file as they are read. The mean only keeps a running sum,
+/// so that memory does not grow with the number of frames; the median needs every frame.
+pub struct FrameCombiner {
+    combine : FrameCombine,
+    /// the first frame, kept as is in case it is the only one
+    first   : Option<Vec<f32>>,
+    /// sum of the frames for the mean, from the second frame on
+    sum     : Vec<f64>,
+    /// every frame for the median, from the second frame on
+    frames  : Vec<Vec<f32>>,
+    count   : usize,
+}
+
+impl FrameCombiner {
+    pub fn new( combine: FrameCombine ) -> FrameCombiner {
+        FrameCombiner {
+            combine : combine,
+            first   : None,
+            sum     : Vec::new(),
+            frames  : Vec::new(),
+            count   : 0,
+        }
+    }
+
+    /// Number of frames added so far
+    pub fn len( &self ) -> usize {
+        self.count
+    }
+
+    pub fn add( &mut self, frame: Vec<f32> ) {
+        self.count += 1;
+        if self.count == 1 {
+            self.first = Some( frame );
+            return;
+        }
+        match self.combine {
+            FrameCombine::Mean => {
+                if let Some( first ) = self.first.take() {
+                    self.sum = first.iter().map( | v | *v as f64 ).collect();
+                }
+                let n = ::std::cmp::min( self.sum.len(), frame.len() );
+                self.sum.truncate( n );
+                for ( sum, v ) in self.sum.iter_mut().zip( frame.iter() ) {
+                    *sum += *v as f64;
+                }
+            },
+            FrameCombine::Median => {
+                if let Some( first ) = self.first.take() {
+                    self.frames.push( first );
+                }
+                self.frames.push( frame );
+            },
+        }
+    }
+
+    /// The combined image, empty when no frame was added
+    pub fn finish( self ) -> Vec<f32> {
+        if let Some( first ) = self.first {
+            return first;
+        }
+        let num_frames = self.count;
+        match self.combine {
+            FrameCombine::Mean => self.sum.iter().map( | sum | ( sum / num_frames as f64 ) as f32 ).collect(),
+            FrameCombine::Median => {
+                let frames = self.frames;
+                let num_pixels = frames.iter().map( | frame | frame.len() ).min().unwrap_or( 0 );
+                let mut combined = Vec::with_capacity( num_pixels );
+                let mut values: Vec<f32> = Vec::with_capacity( num_frames );
+                for i in 0..num_pixels {
+                    values.clear();
+                    values.extend( frames.iter().map( | frame | frame[i] ) );
+                    values.sort_by( | a, b | a.partial_cmp( b ).unwrap_or( Ordering::Equal ) );
+                    let mid = num_frames / 2;
+                    if num_frames % 2 == 1 {
+                        combined.push( values[ mid ] );
+                    } else {
+                        combined.push( ( values[ mid - 1 ] + values[ mid ] ) / 2.0 );
+                    }
+                }
+                combined
+            },
+        }
+    }
+}
@@ -1,177 +1,170 @@
-use std::fs::{DirEntry};
-use std::cmp::Ordering;
+use std::path::PathBuf;
 use image::other::{
     BadType,
-    Pixel,
-    ShortDiagonalStats
+    OpenTestResult,
+    ShortTestResult,
+    DieResult
+};
+
+use image::error::{
+    ImageError,
+    ImageResult
 };
 
 use utils::file::{
-    absolute_difference_of_IDP_Imges
+    absolute_difference_of_IDP_Imges,
+    common_dimensions,
+    read_idp_frame_count,
+    Channel,
+    ChannelMode,
+    TestImage
 };
 
-use super::dimensions::{WIDTH, HEIGHT };
+use utils::pixelops::mark_dead_band;
 
-fn mark_ignored_pixels( ps: &Vec<Pixel>, ignore_edges: usize ) ->  Option< Vec<Pixel> > {
-    let total_pix = ps.len();
-    // assert that this is equal to width times height
-    
-    let mut mask_pixels : Vec<Pixel> = Vec::with_capacity(total_pix); 
-    let mut pit = ps.iter();
-    
-    for row in 0..HEIGHT {
-        if row < ignore_edges || row >= ( HEIGHT - ignore_edges ) {
-            for _col in 0..WIDTH {
-                let ignored_pix   = pit.next().unwrap();
-                let ignored_value = ignored_pix.value;
-                mask_pixels.push( Pixel{ value : ignored_value, valid: BadType::Ignored } );
+use super::clusters::find_clusters;
+use super::dimensions::Dimensions;
+use super::deadband::DeadBand;
+use super::frames::FrameCombine;
+use super::shortlines::{
+    LineOrientation,
+    ShortLines
+};
+use super::threshold::{
+    OpenThreshold,
+    ShortThreshold
+};
+use super::pairing::{
+    bias_code,
+    Pairing,
+    PairTest
+};
+
+// The analysis keeps one plane with the values of a difference image and one plane
+// with the class of every pixel. The class plane is shared by both tests and updated in place.
+
+fn mark_ignored_pixels( dims: &Dimensions, classes: &mut Vec<BadType>, ignore_edges: usize ) {
+    for row in 0..dims.height {
+        let line = &mut classes[ row * dims.width .. ( row + 1 ) * dims.width ];
+        if row < ignore_edges || row >= ( dims.height - ignore_edges ) {
+            for class in line.iter_mut() {
+                *class = BadType::Ignored;
             }
         } else {
-            for col in 0..WIDTH {
-                let this_pix = pit.next().unwrap();
-                let this_valid = this_pix.valid;
-                let this_value = this_pix.value;
-
-                if col < ignore_edges || col >= ( WIDTH - ignore_edges ) {
-                    let mask_pix = if BadType::DeadBand == this_valid {
-                        Pixel{ value : this_value, valid: this_valid  }
-                    } else {
-                        Pixel{ value : this_value, valid: BadType::Ignored } 
-                    };
-                    mask_pixels.push( mask_pix ); 
-                }
-                else {
-                    let mask_pix = Pixel{ value : this_value, valid: this_valid  };
-                    mask_pixels.push( mask_pix );
+            for ( col, class ) in line.iter_mut().enumerate() {
+                if ( col < ignore_edges || col >= ( dims.width - ignore_edges ) ) && BadType::DeadBand != *class {
+                    *class = BadType::Ignored;
                 }
             }
         }
     }
-    Some ( mask_pixels )
 }
 
 
 
-fn mark_open_bads( threshold: f32, ps: &Vec<Pixel> )  ->  ( Option<Vec<Pixel> >, u64 ) {
-    let total_pix = ps.len();
+fn mark_open_bads( dims: &Dimensions, threshold: &OpenThreshold, values: &Vec<f32>, classes: &mut Vec<BadType> )  ->  u64 {
+    let thresholds = threshold.pixel_thresholds( dims, values, classes );
     let mut count = 0u64;
-    let mut marked_pixels : Vec<Pixel> = Vec::with_capacity(total_pix);
-    for p in ps.iter() {
-        let mask_pix = 
-        if p.valid == BadType::Unknown && threshold > p.value {
+    for ( i, ( value, class ) ) in values.iter().zip( classes.iter_mut() ).enumerate() {
+        if *class == BadType::Unknown && thresholds.at( i ) > *value {
             count += 1;
-            Pixel{ value: p.value, valid: BadType::OpenBad }
-        } else {
-            Pixel{ value: p.value, valid: p.valid }
-        };
-        marked_pixels.push( mask_pix );
-    }
-    ( Some( marked_pixels ), count )
-}
-
-fn apply_mask( ms: &Vec<Pixel>, ps: &Vec<Pixel> )  ->  ( Option<Vec<Pixel> > ) {
-    // TODO: assert that they both have the same length or handle mismatch
-    let mut masked_pixels : Vec<Pixel> = Vec::with_capacity( ps.len() );
-    for ( m, p ) in ms.iter().zip( ps.iter() ) {
-        let mask_pix = Pixel{ value: p.value, valid: m.valid };
-        masked_pixels.push( mask_pix );
+            *class = BadType::OpenBad;
+        }
     }
-    Some( masked_pixels )
+    count
 }
 
-fn mark_short_bads( threshold: f32, ps: &Vec<Pixel> )  ->  ( Option<Vec<Pixel> >, u64, usize, usize, usize ) {
-    let total_pix = ps.len();
+// marks the short bads and counts them along every line of each orientation.
+// Returns the number of short bads, of those not on a bad line, of bad lines and of pairs of bad lines next to each other,
+// over all the orientations, and the counts of every line
+fn mark_short_bads( dims: &Dimensions, threshold: f32, values: &Vec<f32>, classes: &mut Vec<BadType>, orientations: &Vec<LineOrientation>, fraction: f32 )
+    ->  ( u64, usize, usize, usize, Vec<ShortLines> ) {
     let mut count = 0u64;
-    let mut marked_pixels : Vec<Pixel> = Vec::with_capacity(total_pix);
-    let mut sdstats: Vec< ShortDiagonalStats > =  Vec::with_capacity( WIDTH + HEIGHT );
-    for _idx in 0..(WIDTH + HEIGHT) {
-        sdstats.push( ShortDiagonalStats { number_of_pixels_measured: 0, number_of_bad_shorts: 0 } )
-    }
-    let mut pit = ps.iter();
-    // for p in ps.iter() {
-    for row in 0..HEIGHT {
-        for col in 0..WIDTH {
-            let _idx = row + col;
-            let p = pit.next().unwrap();
-            let mask_pix = 
-                if p.valid == BadType::Unknown {
-                    sdstats[ _idx ].number_of_pixels_measured += 1;
-                    if threshold > p.value {
+    let mut short_lines = orientations.iter().map( | o | ShortLines::new( *o, fraction, dims ) ).collect::<Vec<ShortLines>>();
+    {
+        let mut pit = values.iter().zip( classes.iter_mut() );
+        for row in 0..dims.height {
+            for col in 0..dims.width {
+                let ( value, class ) = pit.next().unwrap();
+                if *class == BadType::Unknown {
+                    let is_bad = threshold > *value;
+                    if is_bad {
                         count += 1;
-                        sdstats[ _idx ].number_of_bad_shorts += 1;
-                        Pixel{ value: p.value, valid: BadType::ShortBad }
-                    } else { Pixel{ value: p.value, valid: p.valid } }
-                } else {
-                    Pixel{ value: p.value, valid: p.valid }
-                };
-            marked_pixels.push( mask_pix );
+                        *class = BadType::ShortBad;
+                    }
+                    for lines in short_lines.iter_mut() {
+                        let stats = &mut lines.lines[ lines.orientation.line( dims, row, col ) ];
+                        stats.number_of_pixels_measured += 1;
+                        if is_bad {
+                            stats.number_of_bad_shorts += 1;
+                        }
+                    }
+                }
+            }
         }
     }
-    let short_bad_diagonals : Vec<bool> = sdstats.iter().map( | &ShortDiagonalStats { number_of_pixels_measured, number_of_bad_shorts } |  number_of_bad_shorts > ( number_of_pixels_measured / 2 ) ).collect::<Vec<bool>>();
-    let number_of_bad_diagonals = short_bad_diagonals.iter().fold( 0, | sum, flag | sum + if *flag { 1 } else { 0 } );
-    let number_of_short_bads_not_in_bad_diagonals = short_bad_diagonals.iter()
-                                                       .zip( sdstats.iter() )
-                                                       .fold( 0, |sum, ( flag, &ShortDiagonalStats { number_of_pixels_measured, number_of_bad_shorts } )| sum + if  *flag { 0 * number_of_pixels_measured } else { number_of_bad_shorts } );
-    let ( number_of_adjacent_bad_diagonals, _ ) = short_bad_diagonals.iter()
-                                                              .fold( ( 0, false ),  | ( sum, prev_diag_bad ), flag | if *flag && prev_diag_bad { ( sum+1, false ) } else { ( sum, *flag ) } );
-
-    ( Some( marked_pixels ), count, number_of_short_bads_not_in_bad_diagonals as usize, number_of_bad_diagonals, number_of_adjacent_bad_diagonals )
+    let mut number_of_short_bads_not_in_bad_lines = 0;
+    {
+        let mut pit = classes.iter();
+        for row in 0..dims.height {
+            for col in 0..dims.width {
+                if *pit.next().unwrap() == BadType::ShortBad &&
+                   !short_lines.iter().any( | lines | lines.is_bad( lines.orientation.line( dims, row, col ) ) ) {
+                    number_of_short_bads_not_in_bad_lines += 1;
+                }
+            }
+        }
+    }
+    let number_of_bad_lines = short_lines.iter().fold( 0, | sum, lines | sum + lines.number_of_bad_lines() );
+    let number_of_adjacent_bad_lines = short_lines.iter().fold( 0, | sum, lines | sum + lines.number_of_adjacent_bad_lines() );
+
+    ( count, number_of_short_bads_not_in_bad_lines, number_of_bad_lines, number_of_adjacent_bad_lines, short_lines )
 }
 
+// the short difference of the pixels that are still measured after the open test
+fn unmasked_pixel_values( classes: &Vec<BadType>, values: &Vec<f32> ) -> Vec<f32> {
+    classes.iter().zip( values.iter() ).filter_map(
+        | ( c, v ) | if *c == BadType::Unknown { Some( *v ) } else { None }
+    ).collect()
+}
 
-fn _count_bad_pixels( threshold: f32, ms: &Vec<Pixel>, ps: &Vec<Pixel> ) -> u64 {
-    let mut count = 0u64;
 
-    for ( m, p ) in ms.iter().zip( ps.iter() ) {
-        if m.valid == BadType::Unknown && p.valid == BadType::Unknown && threshold > p.value {
-            count += 1;
-        }
-    }
-    count
+// counts of the open test once the bad rows and columns are masked
+struct MaskedLines {
+    number_of_bad_columns           : u64,
+    number_of_bad_rows              : u64,
+    number_of_bad_col_segments      : u64,
+    number_of_bad_row_segments      : u64,
+    number_of_open_bads_in_bad_cols : usize,
+    number_of_open_bads_in_bad_rows : usize,
+    num_measured                    : u64,
+    num_unknown                     : u64,
 }
 
-fn median_of_unmasked_pixel_values ( ms: &Vec<Pixel>, ps: &Vec<Pixel> ) -> Option<f32> {
-
-    let threshold_for_shorts  = {
-        let mut unmasked_pixel_values: Vec<f32> = ms.iter().zip( ps.iter() ).filter_map( 
-            | ( m,p ) | if m.valid == BadType::Unknown { Some( p.value ) } else { None } 
-        ).collect();
-        let num_elems = unmasked_pixel_values.len();
-        // println!( "unmasked_pixel_values.len() = {:?}", num_elems ); 
-        let mid_elem = num_elems / 2;
-        let mid_elem_prev = mid_elem - 1;
-        unmasked_pixel_values.sort_by(| a, b | if a < b { Ordering::Less } else if  a > b { Ordering::Greater } else { Ordering::Equal } );
-        if ( num_elems % 2 ) == 1  { 
-           ( unmasked_pixel_values[ mid_elem ] + unmasked_pixel_values[ mid_elem_prev ] ) / 2.0
-        } else
-        {
-            unmasked_pixel_values[ mid_elem ]
-        }
-    };
-    Some( threshold_for_shorts )
+fn is_measurable( class: BadType ) -> bool {
+    class != BadType::DeadBand && class != BadType::Ignored
 }
 
-
-// masks out all the columns and rows with > 50% bad pixels in them
-// TODO: use a struct to return stuff 
-fn pixels_to_mask( ps: &Vec<Pixel>) ->  Option<( Vec<Pixel>, u64, u64, u64, usize, usize, u64 ) >{
-    let total_pix = ps.len();
-    // assert that this is equal to width times height
+// masks out all the columns and rows with more than `fraction` of their measurable pixels open bad,
+// and the runs of at least `min_run` open bads along the other columns and rows, when `min_run` is not 0
+fn pixels_to_mask( dims: &Dimensions, classes: &mut Vec<BadType>, fraction: f32, min_run: usize ) -> MaskedLines {
+    let total_pix = classes.len();
     
-    let mut bad_pix_in_row : Vec<usize> = Vec::with_capacity( HEIGHT );
-    let mut bad_pix_in_col : Vec<usize> = Vec::with_capacity( WIDTH );
-    for _row in 0..HEIGHT {
-        bad_pix_in_row.push( 0 );
-    }
-    for _col in 0..WIDTH {
-        bad_pix_in_col.push( 0 );
-    }
+    let mut bad_pix_in_row : Vec<usize> = vec![ 0; dims.height ];
+    let mut bad_pix_in_col : Vec<usize> = vec![ 0; dims.width ];
+    let mut measured_in_row : Vec<usize> = vec![ 0; dims.height ];
+    let mut measured_in_col : Vec<usize> = vec![ 0; dims.width ];
 
     {
-        let mut pit = ps.iter();
-        for row in 0..HEIGHT {
-            for col in 0..WIDTH {
-                if BadType::OpenBad == pit.next().unwrap().valid {
+        let mut pit = classes.iter();
+        for row in 0..dims.height {
+            for col in 0..dims.width {
+                let class = *pit.next().unwrap();
+                if is_measurable( class ) {
+                    measured_in_col[ col ] += 1;
+                    measured_in_row[ row ] += 1;
+                }
+                if BadType::OpenBad == class {
                         bad_pix_in_col[ col ] += 1;
                         bad_pix_in_row[ row ] += 1;
                 }
@@ -179,8 +172,9 @@ fn pixels_to_mask( ps: &Vec<Pixel>) ->  Option<( Vec<Pixel>, u64, u64, u64, usiz
         }
     }
 
-    let bad_cols : Vec<bool> = bad_pix_in_col.iter().map( |col_count| *col_count > ( HEIGHT / 2 ) ).collect();
-    let bad_rows : Vec<bool> = bad_pix_in_row.iter().map( |row_count| *row_count > ( WIDTH  / 2 ) ).collect();
+    let is_bad_line = | bad: &usize, measured: &usize | *measured > 0 && *bad as f64 > fraction as f64 * *measured as f64;
+    let bad_cols : Vec<bool> = bad_pix_in_col.iter().zip( measured_in_col.iter() ).map( | ( bad, measured ) | is_bad_line( bad, measured ) ).collect();
+    let bad_rows : Vec<bool> = bad_pix_in_row.iter().zip( measured_in_row.iter() ).map( | ( bad, measured ) | is_bad_line( bad, measured ) ).collect();
 
     let number_of_open_bads_in_bad_cols = 
         bad_cols.iter().zip( bad_pix_in_col.iter() ).fold( 0, |sum, ( flag, count ) | sum + if *flag { *count } else { 0 } );
@@ -191,133 +185,272 @@ fn pixels_to_mask( ps: &Vec<Pixel>) ->  Option<( Vec<Pixel>, u64, u64, u64, usiz
 
     let number_of_bad_columns = bad_cols.iter().fold( 0, |sum, x | sum + if *x { 1 } else {0 } );
     let number_of_bad_rows    = bad_rows.iter().filter( | &x | *x ).collect::<Vec<_>>().len(); // | fold( 0, |sum, x | sum + if *x { 1 } else {0 } );
+
+    // the runs are looked for before the bad lines are masked, in the lines that are not bad as a whole
+    let ( col_segments, row_segments ) = if min_run > 0 {
+        let col_segments = ( 0..dims.width ).filter( | col | !bad_cols[ *col ] )
+                                            .flat_map( | col | open_bad_runs( ( 0..dims.height ).map( | row | row * dims.width + col ), classes, min_run ) )
+                                            .collect::<Vec<Vec<usize>>>();
+        let row_segments = ( 0..dims.height ).filter( | row | !bad_rows[ *row ] )
+                                             .flat_map( | row | open_bad_runs( row * dims.width .. ( row + 1 ) * dims.width, classes, min_run ) )
+                                             .collect::<Vec<Vec<usize>>>();
+        ( col_segments, row_segments )
+    } else {
+        ( vec![], vec![] )
+    };
     
-    let mut mask_pixels : Vec<Pixel> = Vec::with_capacity(total_pix); 
-    // println!( "at line: {:?} ", line!() );
-    let mut pit = ps.iter();
-    // println!( "at line: {:?} ", line!() );
-    let mut num_bad_col = 0u64;
-    let mut num_bad_row = 0u64;
-    let mut num_bad_both = 0u64;
-    let mut num_dead_band = 0u64;
-    let mut num_unknown = 0u64;
-    let mut num_ignored = 0u64; 
-    
-    for row in 0..HEIGHT {
-        for col in 0..WIDTH {
-            let this_pix = pit.next().unwrap();
-            let this_valid = this_pix.valid;
-            let this_value = this_pix.value;
-
-            let mask_pix = match this_valid {
-                BadType::DeadBand => { num_dead_band += 1; Pixel{ value : this_value, valid: this_valid  } },
-                BadType::Ignored  => { num_ignored   += 1; Pixel{ value : this_value, valid: this_valid  } },
-                _ => {
-                    match ( bad_cols[ col ], bad_rows[ row ] ) {
-                        ( true,  true  ) => { num_bad_both += 1; Pixel{ value : 999f32,     valid: BadType::OpenBadBoth } },
-                        ( true,  false ) => { num_bad_col  += 1; Pixel{ value : 999f32,     valid: BadType::OpenBadCol  } },
-                        ( false, true  ) => { num_bad_row  += 1; Pixel{ value : 999f32,     valid: BadType::OpenBadRow  } },
-                        ( false, false ) => { num_unknown  += 1; Pixel{ value : this_value, valid: this_valid           } },
+    {
+        let mut pit = classes.iter_mut();
+        for row in 0..dims.height {
+            for col in 0..dims.width {
+                let class = pit.next().unwrap();
+
+                match *class {
+                    BadType::DeadBand | BadType::Ignored => {},
+                    _ => {
+                        match ( bad_cols[ col ], bad_rows[ row ] ) {
+                            ( true,  true  ) => { *class = BadType::OpenBadBoth; },
+                            ( true,  false ) => { *class = BadType::OpenBadCol;  },
+                            ( false, true  ) => { *class = BadType::OpenBadRow;  },
+                            ( false, false ) => {},
+                        }
                     }
+                };
+            }
+        }
+    }
+    // a pixel of a run may also be in a bad line or in a run the other way
+    for i in col_segments.iter().flat_map( | segment | segment.iter() ) {
+        classes[ *i ] = match classes[ *i ] {
+            BadType::OpenBadRow | BadType::OpenBadBoth => BadType::OpenBadBoth,
+            _ => BadType::OpenBadCol,
+        };
+    }
+    for i in row_segments.iter().flat_map( | segment | segment.iter() ) {
+        classes[ *i ] = match classes[ *i ] {
+            BadType::OpenBadCol | BadType::OpenBadBoth => BadType::OpenBadBoth,
+            _ => BadType::OpenBadRow,
+        };
+    }
+
+    let num_unknown = classes.iter().filter( | class | **class == BadType::Unknown || **class == BadType::OpenBad ).count() as u64;
+    let num_unmeasured = classes.iter().filter( | class | !is_measurable( **class ) ).count() as u64;
+
+    MaskedLines {
+        number_of_bad_columns           : number_of_bad_columns as u64,
+        number_of_bad_rows              : number_of_bad_rows as u64,
+        number_of_bad_col_segments      : col_segments.len() as u64,
+        number_of_bad_row_segments      : row_segments.len() as u64,
+        number_of_open_bads_in_bad_cols : number_of_open_bads_in_bad_cols,
+        number_of_open_bads_in_bad_rows : number_of_open_bads_in_bad_rows,
+        num_measured                    : total_pix as u64 - num_unmeasured,
+        num_unknown                     : num_unknown,
+    }
+}
+
+// the runs of at least `min_run` consecutive open bads along a line, as the indices of their pixels.
+// The dead band and the ignored pixels neither end a run nor count in it.
+fn open_bad_runs<I>( line: I, classes: &Vec<BadType>, min_run: usize ) -> Vec<Vec<usize>> where I: Iterator<Item=usize> {
+    let mut runs = Vec::new();
+    let mut run = Vec::new();
+    for i in line {
+        match classes[ i ] {
+            BadType::OpenBad => run.push( i ),
+            class if is_measurable( class ) => {
+                if run.len() >= min_run {
+                    runs.push( run.clone() );
                 }
-            };
-            mask_pixels.push( mask_pix );
+                run.clear();
+            },
+            _ => {},
         }
     }
-    let _num_total = num_bad_col + num_bad_row + num_bad_both + num_dead_band + num_ignored + num_unknown;
-    let num_measured = total_pix as u64 - ( num_ignored + num_dead_band );
-
-    // println!( " \
-    //     number of bad columns             = {:?} \n \
-    //     number of bad rows                = {:?} \n \
-    //     number of pixels in bad columns   = {:?} \n \
-    //     number of pixels in bad rows      = {:?} \n \
-    //     number o  pixels in bad both      = {:?} \n \
-    //     num_dead_band = {:?} \n \
-    //     num_unknown   = {:?} \n \
-    //     num_total     = {:?} \n" ,
-    //        number_of_bad_columns,
-    //        number_of_bad_rows,
-    //        num_bad_col,
-    //        num_bad_row,
-    //        num_bad_both,
-    //        num_dead_band,
-    //        num_unknown,
-    //        _num_total
-    //    );
-    Some ( ( mask_pixels, number_of_bad_columns as u64, number_of_bad_rows as u64, num_measured as u64, number_of_open_bads_in_bad_cols, number_of_open_bads_in_bad_rows, num_unknown ) )
+    if run.len() >= min_run {
+        runs.push( run );
+    }
+    runs
 }
 
-// TODO: use a struct to get the measurement data out
-pub fn to_diff_pair( file_set : &Vec<DirEntry>, open_threshold: f32, short_threshold: f32, ignore_edges : usize ) -> 
-    ( ( Option<Vec<Pixel> >, Option<Vec<Pixel> > ),
-      ( u64, u64, u64, u64, f32 , u64, usize, usize, usize, usize, usize ) 
-    ) {
-
-    let open_test_files = file_set.iter().filter_map( | this_entry | {
-        let this_entry_path = this_entry.path();
-         if this_entry_path.to_str().unwrap().contains("C1717") 
-         || this_entry_path.to_str().unwrap().contains("C2525") {
-             Some (this_entry )
-         } else {
-                 None
-         }
-    } ).collect::<Vec<&DirEntry>>();
-
-    // println!("open_test_files.len() = {:?}", &open_test_files.len() );
-
-    // TODO: make sure there are at least two files. exactly two?
-    
-    let ( open_diff_pixels, mask_for_shorts, bad_opens, number_of_bad_columns, number_of_bad_rows, num_total, number_of_open_bads_in_bad_cols, number_of_open_bads_in_bad_rows, num_unknown ) =
+// the dead band has no signal, its difference is shown as a large value
+fn fill_dead_band( dims: &Dimensions, dead_band: &DeadBand, values: &mut Vec<f32> ) {
+    for ( i, value ) in values.iter_mut().enumerate() {
+        if dead_band.contains( i / dims.width, i % dims.width, dims ) {
+            *value = 999f32;
+        }
+    }
+}
+
+/// Parameters of the bump bond analysis of a die.
+#[derive(Debug, Clone)]
+pub struct BumpBondAnalysis {
+    /// how pixels of the open difference are found to be open bads
+    pub open_threshold  : OpenThreshold,
+    /// how the value below which pixels of the short difference are short bads is found
+    pub short_threshold : ShortThreshold,
+    /// number of rows/cols to ignore along the edges
+    pub ignore_edges    : usize,
+    pub dead_band       : DeadBand,
+    /// how the frames of files with several frames are combined
+    pub frame_combine   : FrameCombine,
+    /// which images make up the open and the short pair
+    pub pairing         : Pairing,
+    /// which channel the pairs are built from
+    pub channel         : ChannelMode,
+    /// a row or column is bad when more than this fraction of its measured pixels are open bads
+    pub bad_line_fraction : f32,
+    /// runs of at least this many open bads along a row or column are bad segments, 0 for none
+    pub bad_line_run    : usize,
+    /// the directions of the lines the short bads are counted along
+    pub short_lines     : Vec<LineOrientation>,
+    /// a line of the short test is bad when more than this fraction of its measured pixels are short bads
+    pub short_line_fraction : f32,
+}
+
+impl BumpBondAnalysis {
+    pub fn new( open_threshold: OpenThreshold, short_threshold: ShortThreshold, ignore_edges: usize, dead_band: DeadBand ) -> BumpBondAnalysis {
+        BumpBondAnalysis {
+            open_threshold  : open_threshold,
+            short_threshold : short_threshold,
+            ignore_edges    : ignore_edges,
+            dead_band       : dead_band,
+            frame_combine   : FrameCombine::Mean,
+            pairing         : Pairing::default(),
+            channel         : ChannelMode::Reset,
+            bad_line_fraction : 0.5,
+            bad_line_run    : 0,
+            short_lines     : vec![ LineOrientation::AntiDiagonal ],
+            short_line_fraction : 0.5,
+        }
+    }
+
+    /// Runs the open and short tests on the IDP files of one die.
+    pub fn analyse( &self, file_set: &Vec<PathBuf> ) -> ImageResult<DieResult> {
+        to_diff_pair( file_set, self )
+    }
+}
+
+// the two images of a test, there must be exactly two captures of the channel.
+// With CDS each reset capture is matched with the signal capture of the same bias code.
+fn test_pair<'a>( file_set: &'a Vec<PathBuf>, analysis: &BumpBondAnalysis, test: PairTest ) -> ImageResult<( TestImage<'a>, TestImage<'a> )> {
+    let files = analysis.pairing.select( test, file_set );
+    let channel = match analysis.channel {
+        ChannelMode::Signal => Channel::Signal,
+        _ => Channel::Reset,
+    };
+    let captures = files.iter().cloned().filter( | path | Channel::of( path ) == Some( channel ) ).collect::<Vec<&PathBuf>>();
+    if captures.len() != 2 {
+        return Err( ImageError::MissingPairMember(
+            format!( "the {} test needs two {} images of {} but {} were found",
+                     test.name(), channel.name(), analysis.pairing.describe( test ), captures.len() )
+        ) );
+    }
+    match analysis.channel {
+        ChannelMode::Cds => Ok( ( try!( cds_image( captures[0], &files ) ), try!( cds_image( captures[1], &files ) ) ) ),
+        _ => Ok( ( TestImage::Capture( captures[0] ), TestImage::Capture( captures[1] ) ) ),
+    }
+}
+
+fn cds_image<'a>( reset: &'a PathBuf, files: &Vec<&'a PathBuf> ) -> ImageResult<TestImage<'a>> {
+    let code = bias_code( reset );
+    match files.iter().find( | path | Channel::of( path ) == Some( Channel::Signal ) && bias_code( path ) == code ) {
+        Some( signal ) => Ok( TestImage::Cds { signal: signal, reset: reset } ),
+        None => Err( ImageError::MissingPairMember( format!( "no PNSignalOut image for {}", reset.display() ) ) ),
+    }
+}
+
+pub fn to_diff_pair( file_set : &Vec<PathBuf>, analysis: &BumpBondAnalysis ) -> ImageResult<DieResult> {
+
+    // only the C<code> captures of the chosen channel are read
+    let channel_files = file_set.iter().filter( | path | analysis.channel.uses( path ) ).cloned().collect::<Vec<PathBuf>>();
+
+    // all the images of a die are compared pixel by pixel, so they must agree on their size
+    let dims = try!( common_dimensions( &channel_files ) );
+
+    try!( analysis.dead_band.check_dimensions( &dims ) );
+    // 2 * ignore_edges >= width, without overflowing on a huge -i
+    if analysis.ignore_edges >= ( dims.width + 1 ) / 2 || analysis.ignore_edges >= ( dims.height + 1 ) / 2 {
+        return Err( ImageError::DimensionMismatch( format!( "ignoring {} rows and columns along the edges leaves no pixel of the {}x{} image",
+                                                            analysis.ignore_edges, dims.width, dims.height ) ) );
+    }
+    let mut classes = vec![ BadType::Unknown; dims.num_pixels() ];
+    mark_dead_band( &dims, &analysis.dead_band, &mut classes );
+    mark_ignored_pixels( &dims, &mut classes, analysis.ignore_edges );
+    if !classes.contains( &BadType::Unknown ) {
+        return Err( ImageError::FormatError( "the dead band and the ignored edges leave no pixel to measure".to_string() ) );
+    }
+
+    let ( open_diff, open, open_clusters, num_unknown ) =
     { 
-        let mut oit = open_test_files.iter();
-        let lhs = oit.next().unwrap().path();
-        let rhs = oit.next().unwrap().path(); 
-        let open_diff_pix = absolute_difference_of_IDP_Imges( &lhs, &rhs ).expect( "open abs diff failed ");
-        let ig_marked_pixels = mark_ignored_pixels( &open_diff_pix, ignore_edges ).expect(" could not mark ignored pixels");
-        let ( marked_pixels_opt, bad_opens ) = mark_open_bads ( open_threshold, &ig_marked_pixels );
-        let marked_pixels = marked_pixels_opt.expect( "marking open bads failed for open test ");
-        let ( mask_for_shorts, number_of_bad_columns, number_of_bad_rows, num_total, number_of_open_bads_in_bad_cols, number_of_open_bads_in_bad_rows, num_unknown ) 
-                = pixels_to_mask( &marked_pixels ).expect( " unable to create mask" );
-        // print!(" number of bad opens for 1717 - 2525 \n( {:?},\n- {:?} ) = {:?}\n", lhs, rhs, bad_opens );
-        ( open_diff_pix, mask_for_shorts, bad_opens, number_of_bad_columns, number_of_bad_rows, num_total, number_of_open_bads_in_bad_cols, number_of_open_bads_in_bad_rows, num_unknown )
+        let ( lhs, rhs ) = try!( test_pair( &channel_files, analysis, PairTest::Open ) );
+        let ( _, mut open_diff ) = try!( absolute_difference_of_IDP_Imges( &lhs, &rhs, analysis.frame_combine ) );
+        fill_dead_band( &dims, &analysis.dead_band, &mut open_diff );
+        let bad_opens = mark_open_bads ( &dims, &analysis.open_threshold, &open_diff, &mut classes );
+        // masking turns the bad lines into line classes, so the open bads are grouped first
+        let open_clusters = find_clusters( &dims, &classes, BadType::OpenBad );
+        let masked = pixels_to_mask( &dims, &mut classes, analysis.bad_line_fraction, analysis.bad_line_run );
+        let open = OpenTestResult {
+            bad_opens                       : bad_opens,
+            open_threshold                  : analysis.open_threshold.value(),
+            number_of_bad_columns           : masked.number_of_bad_columns,
+            number_of_bad_rows              : masked.number_of_bad_rows,
+            number_of_bad_col_segments      : masked.number_of_bad_col_segments,
+            number_of_bad_row_segments      : masked.number_of_bad_row_segments,
+            number_of_open_bads_in_bad_cols : masked.number_of_open_bads_in_bad_cols,
+            number_of_open_bads_in_bad_rows : masked.number_of_open_bads_in_bad_rows,
+            number_of_pixels_measured       : masked.num_measured,
+        };
+        ( open_diff, open, open_clusters, masked.num_unknown )
     };
-    let short_test_files = &file_set.iter().filter_map( | this_entry | {
-        let this_entry_path = this_entry.path();
-         if this_entry_path.to_str().unwrap().contains("C1725") 
-         || this_entry_path.to_str().unwrap().contains("C2517") {
-             Some (this_entry )
-         } else {
-                 None
-         }
-    } ).collect::<Vec<&DirEntry>>();
-
-    // TODO: make sure there are at least two files. exactly two?
-
-    let ( short_diff_pixels, bad_shorts, threshold_for_shorts, number_of_short_bads_not_in_bad_diagonals, number_of_bad_diagonals, number_of_adjacent_bad_diagonals ) =
+    let ( short_diff, short, short_clusters, short_lines ) =
     { 
         if num_unknown > 0  
         {
-            let mut sit = short_test_files.iter();
-            let lhs = sit.next().unwrap().path();
-            let rhs = sit.next().unwrap().path(); 
-            // println!(" short_test_files:\n {:?},\n {:?} ", &lhs, &rhs );
-            let short_diff_pix = absolute_difference_of_IDP_Imges( &lhs, &rhs ).expect( "short abs diff failed ");
-            let threshold_for_shorts  = short_threshold * median_of_unmasked_pixel_values( &mask_for_shorts, &short_diff_pix ).expect(" unable to collect unmasked pixels");
+            let ( lhs, rhs ) = try!( test_pair( &channel_files, analysis, PairTest::Short ) );
+            let ( _, mut short_diff ) = try!( absolute_difference_of_IDP_Imges( &lhs, &rhs, analysis.frame_combine ) );
+            fill_dead_band( &dims, &analysis.dead_band, &mut short_diff );
+            let threshold_for_shorts = match analysis.short_threshold.threshold( unmasked_pixel_values( &classes, &short_diff ) ) {
+                Some( threshold ) => threshold,
+                None => return Err( ImageError::FormatError( "the short difference has no measured pixel to take the threshold from".to_string() ) ),
+            };
             
-            let masked_short_diff_pix = apply_mask( &mask_for_shorts, &short_diff_pix ).expect(" Unable to apply openmask to short diffs " );
-            let ( _marked_short_pixels_opt, num_bad_shorts, number_of_short_bads_not_in_bad_diagonals, number_of_bad_diagonals, number_of_adjacent_bad_diagonals ) = 
-                mark_short_bads( threshold_for_shorts, &masked_short_diff_pix );
-            ( short_diff_pix, num_bad_shorts, threshold_for_shorts, number_of_short_bads_not_in_bad_diagonals, number_of_bad_diagonals, number_of_adjacent_bad_diagonals )
+            let ( num_bad_shorts, number_of_short_bads_not_in_bad_lines, number_of_bad_lines, number_of_adjacent_bad_lines, short_lines ) = 
+                mark_short_bads( &dims, threshold_for_shorts, &short_diff, &mut classes, &analysis.short_lines, analysis.short_line_fraction );
+            let short = ShortTestResult {
+                bad_shorts                            : num_bad_shorts,
+                threshold_for_shorts                  : threshold_for_shorts,
+                number_of_short_bads_not_in_bad_lines : number_of_short_bads_not_in_bad_lines,
+                number_of_bad_lines                   : number_of_bad_lines,
+                number_of_adjacent_bad_lines          : number_of_adjacent_bad_lines,
+            };
+            let short_clusters = find_clusters( &dims, &classes, BadType::ShortBad );
+            ( short_diff, short, short_clusters, short_lines )
         } else {
-            ( vec![], 0u64, 0.0f32, 0usize, 0usize, 0usize )
+            ( vec![], ShortTestResult {
+                bad_shorts                            : 0u64,
+                threshold_for_shorts                  : 0.0f32,
+                number_of_short_bads_not_in_bad_lines : 0usize,
+                number_of_bad_lines                   : 0usize,
+                number_of_adjacent_bad_lines          : 0usize,
+            }, vec![], vec![] )
         }
 
     };
-    (
-        ( Some( open_diff_pixels ) , Some( short_diff_pixels ) ),
-        ( bad_opens, number_of_bad_columns, number_of_bad_rows, bad_shorts, threshold_for_shorts , num_total, number_of_open_bads_in_bad_cols, number_of_open_bads_in_bad_rows, number_of_short_bads_not_in_bad_diagonals, number_of_bad_diagonals, number_of_adjacent_bad_diagonals )
-
-    )
+    // counted once the pixels could be read, so that a broken file is reported by the analysis
+    let mut frames = None;
+    for path in channel_files.iter() {
+        let count = try!( read_idp_frame_count( path ) );
+        frames = Some( frames.map_or( count, | f: usize | ::std::cmp::min( f, count ) ) );
+    }
+    Ok( DieResult {
+        dims           : dims,
+        frames         : frames.unwrap_or( 0 ),
+        open           : open,
+        short          : short,
+        open_diff      : open_diff,
+        short_diff     : short_diff,
+        defects        : classes,
+        open_clusters  : open_clusters,
+        short_clusters : short_clusters,
+        short_lines    : short_lines,
+    } )
 }
-
@@ -0,0 +1,98 @@
+use std::any::Any;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::channel;
+use std::thread;
+
+/// Runs `work` on every item on up to `jobs` threads and hands the results to `done`
+/// in the order of `items`, as `done( index, result )`.
+/// Every thread takes one item at a time, so no more than `jobs` items are worked on at once.
+/// Results that are ready before the ones in front of them are kept until those are done.
+/// A panic of `work` only fails its own item: the result is then Err with the message of the panic.
+pub fn for_each_in_order<T, R, W, D>( items: Vec<T>, jobs: usize, work: W, mut done: D )
+    where T: Send + 'static,
+          R: Send + 'static,
+          W: Fn( T ) -> R + Send + Sync + 'static,
+          D: FnMut( usize, Result<R, String> )
+{
+    let jobs = cmp::max( 1, cmp::min( jobs, items.len() ) );
+    if jobs == 1 {
+        for ( i, item ) in items.into_iter().enumerate() {
+            done( i, run( &work, item ) );
+        }
+        return;
+    }
+
+    let queue = Arc::new( Mutex::new( items.into_iter().enumerate() ) );
+    let work = Arc::new( work );
+    let ( tx, rx ) = channel();
+    let mut workers = Vec::with_capacity( jobs );
+    for _ in 0..jobs {
+        let queue = queue.clone();
+        let work  = work.clone();
+        let tx    = tx.clone();
+        workers.push( thread::spawn( move || {
+            loop {
+                // the lock is only held while taking the next item
+                let next = queue.lock().unwrap().next();
+                match next {
+                    Some( ( i, item ) ) => if tx.send( ( i, run( &*work, item ) ) ).is_err() { break; },
+                    None => break,
+                }
+            }
+        } ) );
+    }
+    drop( tx );
+
+    let mut pending = BTreeMap::new();
+    let mut next = 0usize;
+    for ( i, result ) in rx.iter() {
+        pending.insert( i, result );
+        while let Some( result ) = pending.remove( &next ) {
+            done( next, result );
+            next += 1;
+        }
+    }
+    for worker in workers {
+        if worker.join().is_err() {
+            panic!( "a worker thread panicked" );
+        }
+    }
+}
+
+// the work on one item, with a panic turned into its message
+fn run<T, R, W: Fn( T ) -> R>( work: &W, item: T ) -> Result<R, String> {
+    panic::catch_unwind( AssertUnwindSafe( || work( item ) ) ).map_err( | payload | panic_message( &*payload ) )
+}
+
+fn panic_message( payload: &( dyn Any + Send ) ) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some( message ) => message.to_string(),
+        None => payload.downcast_ref::<String>().cloned().unwrap_or( "unknown panic".to_string() ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::for_each_in_order;
+
+    #[test]
+    fn a_panic_only_fails_its_item() {
+        for &jobs in [ 1, 3 ].iter() {
+            let mut results = Vec::new();
+            for_each_in_order( ( 0..10 ).collect::<Vec<usize>>(), jobs,
+                | i | if i == 4 { panic!( "die {} is broken", i ) } else { i * 2 },
+                | i, result | results.push( ( i, result ) ) );
+            assert_eq!( results.len(), 10 );
+            for &( i, ref result ) in results.iter() {
+                match *result {
+                    Ok( v ) => assert_eq!( ( i, v ), ( i, i * 2 ) ),
+                    Err( ref e ) => assert_eq!( ( i, &e[..] ), ( 4, "die 4 is broken" ) ),
+                }
+            }
+            assert_eq!( results.iter().map( | r | r.0 ).collect::<Vec<usize>>(), ( 0..10 ).collect::<Vec<usize>>() );
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::cmp;
+use std::fs;
+use std::io;
+use std::io::{Error, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use super::binning::Bin;
+use super::file::CaptureName;
+use super::report::{
+    DieRecord,
+    Value,
+    COLUMNS
+};
+
+/// Number of dies listed as the worst of the lot summary
+pub const WORST_DIES: usize = 10;
+
+/// A directory with the dies of one wafer, and the names the output gives to its lot and itself
+#[derive(Debug, Clone)]
+pub struct WaferDir {
+    pub lot   : String,
+    pub wafer : String,
+    pub dir   : PathBuf,
+}
+
+impl WaferDir {
+    /// Takes the names of the lot and the wafer from the L and W fields of the names of `captures`.
+    /// The capture software writes a bare `L` or `W` when it was given none, such a field keeps the name of the directory,
+    /// and so do fields that differ between the captures, with a warning.
+    pub fn name_from_captures<'a, I>( &mut self, captures: I ) where I: Iterator<Item = &'a PathBuf> {
+        let mut lots = Vec::new();
+        let mut wafers = Vec::new();
+        for capture in captures.filter_map( | path | CaptureName::parse( path ).ok() ) {
+            if capture.lot != "L" && !lots.contains( &capture.lot ) {
+                lots.push( capture.lot.clone() );
+            }
+            if capture.wafer != "W" && !wafers.contains( &capture.wafer ) {
+                wafers.push( capture.wafer );
+            }
+        }
+        let dir = self.dir.display().to_string();
+        name_from_fields( &mut self.lot, lots, "lot", &dir );
+        name_from_fields( &mut self.wafer, wafers, "wafer", &dir );
+    }
+}
+
+fn name_from_fields( name: &mut String, fields: Vec<String>, what: &str, dir: &str ) {
+    match fields.len() {
+        0 => {},
+        1 => *name = fields.into_iter().next().unwrap(),
+        _ => {
+            let _ = writeln!( io::stderr(), "warning: the captures of {} name the {}s {}, it is named {} after its directory",
+                              dir, what, fields.join( ", " ), name );
+        },
+    }
+}
+
+/// The wafers of a run: every directory of `wafers` is a wafer of the lot named after its parent directory,
+/// and every subdirectory of a directory of `lots` is a wafer of that lot, in the order of their names.
+/// These names are only the last resort, see `WaferDir::name_from_captures`.
+pub fn wafer_dirs( wafers: &[String], lots: &[String] ) -> io::Result<Vec<WaferDir>> {
+    let mut dirs = Vec::new();
+    for wafer in wafers.iter() {
+        let dir = PathBuf::from( wafer );
+        let lot = dir.canonicalize().ok().and_then( | d | d.parent().map( | p | dir_name( p ) ) ).unwrap_or( String::new() );
+        dirs.push( WaferDir { lot: lot, wafer: dir_name( &dir ), dir: dir } );
+    }
+    for lot in lots.iter() {
+        let root = Path::new( lot );
+        if !root.is_dir() {
+            return Err( Error::new( ErrorKind::Other, format!( "The given lot {:?} is not a directory or is not accessible", root ) ) );
+        }
+        let mut subdirs = try!( fs::read_dir( root ).and_then( | entries | entries.map( | e | e.map( | e | e.path() ) ).collect::<io::Result<Vec<PathBuf>>>() ) );
+        subdirs.retain( | path | path.is_dir() );
+        subdirs.sort();
+        for dir in subdirs.into_iter() {
+            dirs.push( WaferDir { lot: dir_name( root ), wafer: dir_name( &dir ), dir: dir } );
+        }
+    }
+    Ok( dirs )
+}
+
+// the last component of a path, `.` and `..` included, so that `-t .` is named after the current directory
+fn dir_name( dir: &Path ) -> String {
+    let dir = dir.canonicalize().unwrap_or( dir.to_path_buf() );
+    dir.file_name().map( | n | n.to_string_lossy().into_owned() ).unwrap_or( String::new() )
+}
+
+/// Counts and metrics of the dies of one wafer
+#[derive(Debug, Clone)]
+pub struct WaferSummary {
+    pub lot      : String,
+    pub wafer    : String,
+    pub dies     : usize,
+    /// dies that could not be analysed
+    pub failed   : usize,
+    /// GOOD dies, None when no spec limits were given
+    pub good     : Option<usize>,
+    /// mean and median of every metric over the dies that were analysed, None when there is none
+    pub metrics  : Vec<Option<( f64, f64 )>>,
+}
+
+impl WaferSummary {
+    /// Fraction of the dies that are GOOD, None when no spec limits were given
+    pub fn yield_fraction( &self ) -> Option<f64> {
+        self.good.map( | good | if self.dies == 0 { 0.0 } else { good as f64 / self.dies as f64 } )
+    }
+}
+
+/// What the run ends with: every wafer, and the dies with the largest value of the first metric
+#[derive(Debug, Clone)]
+pub struct LotSummary {
+    pub metrics : Vec<String>,
+    pub wafers  : Vec<WaferSummary>,
+    /// largest first, at most `WORST_DIES`
+    pub worst   : Vec<( DieRecord, f64 )>,
+}
+
+impl LotSummary {
+    /// Summarises the records of every wafer, in the order of the first die of each wafer.
+    /// `metrics` must be numeric fields of the output, see `check_metric`.
+    pub fn new( metrics: &[String], records: &Vec<DieRecord> ) -> LotSummary {
+        let indices = metrics.iter().map( | m | COLUMNS.iter().position( | c | c == m ).unwrap_or( 0 ) ).collect::<Vec<usize>>();
+        let has_spec = records.iter().any( | r | r.bin.is_some() );
+        let mut wafers: Vec<( ( &str, &str ), Vec<&DieRecord> )> = Vec::new();
+        for record in records.iter() {
+            let key = ( &record.lot[..], &record.wafer[..] );
+            match wafers.iter().position( | w | w.0 == key ) {
+                Some( i ) => wafers[i].1.push( record ),
+                None => wafers.push( ( key, vec![ record ] ) ),
+            }
+        }
+        let summaries = wafers.iter().map( | &( ( lot, wafer ), ref dies ) | {
+            let metrics = indices.iter().map( | i | mean_and_median( dies.iter().filter_map( | r | metric_value( r, *i ) ).collect() ) ).collect();
+            WaferSummary {
+                lot     : lot.to_string(),
+                wafer   : wafer.to_string(),
+                dies    : dies.len(),
+                failed  : dies.iter().filter( | r | r.summary.is_none() ).count(),
+                good    : if has_spec { Some( dies.iter().filter( | r | r.bin == Some( Bin::Good ) ).count() ) } else { None },
+                metrics : metrics,
+            }
+        } ).collect();
+
+        let mut worst = match indices.first() {
+            Some( i ) => records.iter().filter_map( | r | metric_value( r, *i ).map( | v | ( r.clone(), v ) ) ).collect::<Vec<( DieRecord, f64 )>>(),
+            None => Vec::new(),
+        };
+        // stable, so that dies with the same value stay in the order of the output
+        worst.sort_by( | a, b | b.1.partial_cmp( &a.1 ).unwrap_or( cmp::Ordering::Equal ) );
+        worst.truncate( WORST_DIES );
+        LotSummary {
+            metrics : metrics.to_vec(),
+            wafers  : summaries,
+            worst   : worst,
+        }
+    }
+
+    /// Writes the wafers and the worst dies as aligned tables.
+    pub fn write<W: Write>( &self, w: &mut W ) -> io::Result<()> {
+        let has_spec = self.wafers.iter().any( | s | s.good.is_some() );
+        let mut header = vec![ "lot".to_string(), "wafer".to_string(), "dies".to_string(), "failed".to_string() ];
+        if has_spec {
+            header.push( "good".to_string() );
+            header.push( "yield".to_string() );
+        }
+        for metric in self.metrics.iter() {
+            header.push( format!( "mean_{}", metric ) );
+            header.push( format!( "median_{}", metric ) );
+        }
+        let mut rows = vec![ header ];
+        for wafer in self.wafers.iter() {
+            let mut row = vec![ wafer.lot.clone(), wafer.wafer.clone(), wafer.dies.to_string(), wafer.failed.to_string() ];
+            if has_spec {
+                row.push( wafer.good.unwrap_or( 0 ).to_string() );
+                row.push( format!( "{:.1}%", 100.0 * wafer.yield_fraction().unwrap_or( 0.0 ) ) );
+            }
+            for metric in wafer.metrics.iter() {
+                match *metric {
+                    Some( ( mean, median ) ) => { row.push( format!( "{:.2}", mean ) ); row.push( format!( "{:.2}", median ) ); },
+                    None => { row.push( "-".to_string() ); row.push( "-".to_string() ); },
+                }
+            }
+            rows.push( row );
+        }
+        try!( writeln!( w, "lot summary" ) );
+        try!( write_table( w, &rows ) );
+
+        if let Some( metric ) = self.metrics.first() {
+            let mut rows = vec![ vec![ "lot".to_string(), "wafer".to_string(), "x".to_string(), "y".to_string(), metric.clone() ] ];
+            if has_spec {
+                rows[0].push( "bin".to_string() );
+            }
+            for &( ref record, value ) in self.worst.iter() {
+                let mut row = vec![ record.lot.clone(), record.wafer.clone(), record.x.to_string(), record.y.to_string(), value.to_string() ];
+                if has_spec {
+                    row.push( record.bin.map( | b | b.name() ).unwrap_or( "" ).to_string() );
+                }
+                rows.push( row );
+            }
+            try!( writeln!( w, "worst dies by {}", metric ) );
+            try!( write_table( w, &rows ) );
+        }
+        Ok( () )
+    }
+}
+
+// the value of the field at `index` of a die that was analysed
+fn metric_value( record: &DieRecord, index: usize ) -> Option<f64> {
+    if record.summary.is_none() {
+        return None;
+    }
+    match record.values()[ index ] {
+        Value::Int( v )   => Some( v as f64 ),
+        Value::Float( v ) if !v.is_nan() => Some( v ),
+        _ => None,
+    }
+}
+
+fn mean_and_median( mut values: Vec<f64> ) -> Option<( f64, f64 )> {
+    if values.is_empty() {
+        return None;
+    }
+    let n = values.len();
+    let mean = values.iter().fold( 0.0, | sum, v | sum + v ) / n as f64;
+    values.sort_by( | a, b | a.partial_cmp( b ).unwrap_or( cmp::Ordering::Equal ) );
+    let median = if n % 2 == 1 { values[ n / 2 ] } else { ( values[ n / 2 - 1 ] + values[ n / 2 ] ) / 2.0 };
+    Some( ( mean, median ) )
+}
+
+// left aligned columns two spaces apart, indented like the yield summary
+fn write_table<W: Write>( w: &mut W, rows: &Vec<Vec<String>> ) -> io::Result<()> {
+    let columns = rows.iter().map( | r | r.len() ).max().unwrap_or( 0 );
+    let widths = ( 0..columns ).map( | c | rows.iter().filter_map( | r | r.get( c ) ).map( | t | t.len() ).max().unwrap_or( 0 ) ).collect::<Vec<usize>>();
+    for row in rows.iter() {
+        let fields = row.iter().zip( widths.iter() ).map( | ( text, width ) | format!( "{:<1$}", text, width ) ).collect::<Vec<String>>();
+        try!( writeln!( w, "  {}", fields.join( "  " ).trim_right() ) );
+    }
+    Ok( () )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wafer( captures: &[&str] ) -> WaferDir {
+        let mut wafer = WaferDir { lot: "parent".to_string(), wafer: "dir".to_string(), dir: PathBuf::from( "parent/dir" ) };
+        let paths = captures.iter().map( | name | Path::new( "parent/dir/x1y1" ).join( name ) ).collect::<Vec<PathBuf>>();
+        wafer.name_from_captures( paths.iter() );
+        wafer
+    }
+
+    #[test]
+    fn names_come_from_the_captures() {
+        let w = wafer( &[ "LOT7_W02_X1_Y1_D1_C1717_PNResetOut_O3_BDx3_T150707111948.IDP",
+                          "LOT7_W02_X1_Y1_D1_C1725_PNResetOut_O3_BDx3_T150707112310.IDP",
+                          "commCaptureOutputBuffer.IDP" ] );
+        assert_eq!( ( w.lot.as_str(), w.wafer.as_str() ), ( "LOT7", "W02" ) );
+    }
+
+    #[test]
+    fn placeholders_keep_the_directory_names() {
+        let w = wafer( &[ "L_W_X1_Y1_D1_C1717_PNResetOut_O3_BDx3_T150707111948.IDP" ] );
+        assert_eq!( ( w.lot.as_str(), w.wafer.as_str() ), ( "parent", "dir" ) );
+        let w = wafer( &[ "LOT7_W_X1_Y1_D1_C1717_PNResetOut_O3_BDx3_T150707111948.IDP" ] );
+        assert_eq!( ( w.lot.as_str(), w.wafer.as_str() ), ( "LOT7", "dir" ) );
+        let w = wafer( &[] );
+        assert_eq!( ( w.lot.as_str(), w.wafer.as_str() ), ( "parent", "dir" ) );
+    }
+
+    #[test]
+    fn captures_that_disagree_keep_the_directory_names() {
+        let w = wafer( &[ "LOT7_W02_X1_Y1_D1_C1717_PNResetOut_O3_BDx3_T150707111948.IDP",
+                          "LOT8_W02_X1_Y1_D1_C1725_PNResetOut_O3_BDx3_T150707112310.IDP" ] );
+        assert_eq!( ( w.lot.as_str(), w.wafer.as_str() ), ( "parent", "W02" ) );
+    }
+}
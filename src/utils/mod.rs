@@ -3,4 +3,20 @@ pub mod imageops;
 pub mod pixelops;
 pub mod claptions;
 pub mod dimensions;
+pub mod deadband;
+pub mod defectmap;
+pub mod frames;
+pub mod jobs;
+pub mod binning;
+pub mod pairing;
+pub mod stats;
+pub mod threshold;
+pub mod report;
+pub mod wafermap;
+pub mod clusters;
+pub mod shortlines;
+pub mod recipe;
+pub mod discovery;
+pub mod lot;
+pub mod cache;
 // pub mod cmdline_options;
\ No newline at end of file
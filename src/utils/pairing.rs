@@ -0,0 +1,122 @@
+use std::path::{Path, PathBuf};
+
+use image::error::{
+    ImageError,
+    ImageResult
+};
+
+use super::file::spec_lines;
+
+/// The two tests that are run on a pair of images
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub enum PairTest {
+    Open,
+    Short,
+}
+
+impl PairTest {
+    pub fn name( &self ) -> &'static str {
+        match *self {
+            PairTest::Open  => "open",
+            PairTest::Short => "short",
+        }
+    }
+}
+
+/// Bias code `C<v1><v2>` of a file name, e.g. `C1725` is ( "17", "25" ).
+/// It is the `_` separated word made of a `C` and an even number of digits,
+/// split in two halves of the same length.
+pub fn bias_code( path: &Path ) -> Option<( String, String )> {
+    let name = match path.file_name() {
+        Some( name ) => name.to_string_lossy().into_owned(),
+        None => return None,
+    };
+    name.split( '_' ).filter_map( parse_bias_code ).next()
+}
+
+/// Splits a bias code like `C1725` into its two voltage codes.
+pub fn parse_bias_code( word: &str ) -> Option<( String, String )> {
+    if !word.starts_with( 'C' ) {
+        return None;
+    }
+    let digits = &word[1..];
+    if digits.is_empty() || digits.len() % 2 != 0 || !digits.chars().all( | c | c.is_digit( 10 ) ) {
+        return None;
+    }
+    let half = digits.len() / 2;
+    Some( ( digits[..half].to_string(), digits[half..].to_string() ) )
+}
+
+/// Which images of a die make up the open and the short pair.
+/// Without rules, the pair with v1 == v2 is the open pair and the pair with v1 != v2 the short pair.
+#[derive(Debug, Clone, Default)]
+pub struct Pairing {
+    /// bias codes of the open pair, e.g. `C1717` and `C2525`
+    pub open  : Vec<String>,
+    /// bias codes of the short pair, e.g. `C1725` and `C2517`
+    pub short : Vec<String>,
+}
+
+impl Pairing {
+    /// Parses pairing rules, one per line of `spec_lines`. A rule is `open <code> <code>` or `short <code> <code>`.
+    /// A test without a rule is paired by v1 == v2 / v1 != v2.
+    pub fn from_spec( spec: &str ) -> ImageResult<Pairing> {
+        let mut pairing = Pairing::default();
+        for line in try!( spec_lines( spec ) ).iter() {
+            let line = &line[..];
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let test = match ( words[0], words.len() ) {
+                ( "open",  3 ) => PairTest::Open,
+                ( "short", 3 ) => PairTest::Short,
+                _ => return Err( pairing_error( line, "expected open <code> <code> or short <code> <code>" ) ),
+            };
+            for code in words[1..].iter() {
+                if parse_bias_code( code ).is_none() {
+                    return Err( pairing_error( line, &format!( "{:?} is not a bias code like C1725", code ) ) );
+                }
+            }
+            let codes = vec![ words[1].to_string(), words[2].to_string() ];
+            match test {
+                PairTest::Open  => pairing.open  = codes,
+                PairTest::Short => pairing.short = codes,
+            }
+        }
+        Ok( pairing )
+    }
+
+    /// The files of `file_set` that belong to the pair of `test`
+    pub fn select<'a>( &self, test: PairTest, file_set: &'a Vec<PathBuf> ) -> Vec<&'a PathBuf> {
+        let codes = self.codes( test );
+        file_set.iter().filter( | path | match bias_code( path ) {
+            Some( ( v1, v2 ) ) => if codes.is_empty() {
+                ( v1 == v2 ) == ( test == PairTest::Open )
+            } else {
+                codes.iter().any( | code | *code == format!( "C{}{}", v1, v2 ) )
+            },
+            None => false,
+        } ).collect()
+    }
+
+    /// The bias codes of the pair of `test`, as shown in error messages
+    pub fn describe( &self, test: PairTest ) -> String {
+        let codes = self.codes( test );
+        if !codes.is_empty() {
+            codes.join( " / " )
+        } else if test == PairTest::Open {
+            "C<v1><v2> with v1 == v2".to_string()
+        } else {
+            "C<v1><v2> with v1 != v2".to_string()
+        }
+    }
+
+    fn codes( &self, test: PairTest ) -> &Vec<String> {
+        match test {
+            PairTest::Open  => &self.open,
+            PairTest::Short => &self.short,
+        }
+    }
+}
+
+fn pairing_error( line: &str, msg: &str ) -> ImageError {
+    ImageError::FormatError( format!( "invalid pairing rule {:?}: {}", line, msg ) )
+}
@@ -1,41 +1,23 @@
+use image::other::BadType;
 
-use image::other::{
-    BadType,
-    Pixel
-};
+use super::dimensions::Dimensions;
+use super::deadband::DeadBand;
 
-use super::dimensions::{WIDTH, HEIGHT };
-
-fn is_dead_band( i: usize, width: usize, height: usize ) -> Option<bool> {
-    if ! ( WIDTH == width && HEIGHT == height ) {
-        return None; // also check for i being in bounds
+fn is_dead_band( i: usize, dims: &Dimensions, dead_band: &DeadBand ) -> Option<bool> {
+    if i >= dims.num_pixels() {
+        return None;
     } else {
-        let row = i / width;
-        let col = i % width;
-        let crow = HEIGHT - row;
-        if col >= crow && col <= ( crow + 231 ) {
-            Some ( true )
-        } else {
-            Some ( false )
-        }
+        let row = i / dims.width;
+        let col = i % dims.width;
+        Some( dead_band.contains( row, col, dims ) )
     }
 }
 
-pub fn make_pixel_u16( (i, val ) : ( usize, &u16 ) ) -> Pixel {
-    if is_dead_band( i, WIDTH, HEIGHT ).unwrap() {
-        Pixel { value : val.clone() as f32, valid : BadType::DeadBand }
-    }  else
-    {
-        Pixel { value : val.clone() as f32, valid : BadType::Unknown }
-    }
-}
-
-
-pub fn make_pixel_f32( (i, val ) : ( usize, &f32 ) ) -> Pixel {
-    if is_dead_band( i, WIDTH, HEIGHT ).expect( "is dead band paniced" ) {
-        Pixel { value : val.clone() as f32, valid : BadType::DeadBand }
-    }  else
-    {
-        Pixel { value : val.clone() as f32, valid : BadType::Unknown }
+/// Sets the class of every pixel in the dead band to `BadType::DeadBand`.
+pub fn mark_dead_band( dims: &Dimensions, dead_band: &DeadBand, classes: &mut Vec<BadType> ) {
+    for ( i, class ) in classes.iter_mut().enumerate() {
+        if is_dead_band( i, dims, dead_band ).unwrap_or( false ) {
+            *class = BadType::DeadBand;
+        }
     }
 }
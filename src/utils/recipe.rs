@@ -0,0 +1,286 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use image::error::{
+    ImageError,
+    ImageResult
+};
+
+/// The options that a recipe can set, by their long name on the command line
+pub const RECIPE_KEYS: &'static [&'static str] = &[
+    "open_threshold", "open_method", "short_threshold", "short_method",
+    "ignore_edges", "dead_band", "frames", "pairing", "channel",
+    "bad_line_fraction", "bad_line_run", "short_lines", "short_line_fraction",
+    "include", "exclude", "die_pattern",
+    "spec", "jobs", "format", "output", "defect_maps", "clusters", "wafer_map", "wafer_metrics",
+    "cache",
+];
+
+/// Settings of an analysis read from a file, each as the text that would be given to its option.
+/// An option given on the command line wins over the recipe.
+#[derive(Debug, Clone, Default)]
+pub struct Recipe {
+    pub entries : Vec<( String, String )>,
+}
+
+impl Recipe {
+    /// Reads a recipe, as JSON when the file name ends in `.json` and else as TOML.
+    /// Both hold one value per option at the top level, e.g. `open_threshold = 0.3` or `{ "open_threshold": 0.3 }`.
+    /// Only this flat subset of the formats is read: no tables, arrays, dotted keys or multi-line strings.
+    pub fn read( path: &Path ) -> ImageResult<Recipe> {
+        read_recipe( path ).map_err( | e | e.for_file( path ) )
+    }
+
+    pub fn get( &self, key: &str ) -> Option<&str> {
+        self.entries.iter().find( | &&( ref k, _ ) | k == key ).map( | &( _, ref v ) | &v[..] )
+    }
+}
+
+fn read_recipe( path: &Path ) -> ImageResult<Recipe> {
+    let mut text = String::new();
+    let mut f = try!( File::open( path ) );
+    try!( f.read_to_string( &mut text ) );
+    let is_json = path.extension().map( | e | e.to_string_lossy().to_lowercase() == "json" ).unwrap_or( false );
+    parse_recipe( &text, is_json )
+}
+
+fn parse_recipe( text: &str, is_json: bool ) -> ImageResult<Recipe> {
+    let entries = try!( if is_json { parse_json( text ) } else { parse_toml( text ) } );
+
+    let mut recipe = Recipe::default();
+    for ( key, value ) in entries.into_iter() {
+        if !RECIPE_KEYS.contains( &&key[..] ) {
+            return Err( recipe_error( &key, &format!( "is not an option of a recipe, use one of {}", RECIPE_KEYS.join( ", " ) ) ) );
+        }
+        if recipe.get( &key ).is_some() {
+            return Err( recipe_error( &key, "is given twice" ) );
+        }
+        // null in JSON leaves the option at its default
+        if let Some( value ) = value {
+            recipe.entries.push( ( key, value ) );
+        }
+    }
+    Ok( recipe )
+}
+
+fn recipe_error( key: &str, msg: &str ) -> ImageError {
+    ImageError::FormatError( format!( "invalid recipe: {:?} {}", key, msg ) )
+}
+
+fn syntax_error( msg: &str ) -> ImageError {
+    ImageError::FormatError( format!( "invalid recipe: {}", msg ) )
+}
+
+// `key = value` lines, with `#` comments. The values are strings in "" or '', numbers or booleans.
+fn parse_toml( text: &str ) -> ImageResult<Vec<( String, Option<String> )>> {
+    let mut entries = Vec::new();
+    for ( n, line ) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with( '#' ) {
+            continue;
+        }
+        let at_line = | msg: &str | syntax_error( &format!( "line {}: {}", n + 1, msg ) );
+        if line.starts_with( '[' ) {
+            return Err( at_line( "tables are not used, write every option at the top level" ) );
+        }
+        let eq = match line.find( '=' ) {
+            Some( eq ) => eq,
+            None => return Err( at_line( "expected <option> = <value>" ) ),
+        };
+        let key = line[..eq].trim();
+        if key.is_empty() || !key.chars().all( | c | c.is_alphanumeric() || c == '_' || c == '-' ) {
+            return Err( at_line( &format!( "{:?} is not an option name", key ) ) );
+        }
+        let mut chars = line[ eq + 1.. ].trim().chars().peekable();
+        let value = match chars.peek().cloned() {
+            Some( '"' ) => { chars.next(); try!( read_quoted( &mut chars, '"', true ).map_err( | e | at_line( &e ) ) ) },
+            Some( '\'' ) => { chars.next(); try!( read_quoted( &mut chars, '\'', false ).map_err( | e | at_line( &e ) ) ) },
+            _ => {
+                let word = read_word( &mut chars, | c | c.is_whitespace() || c == '#' );
+                if word.is_empty() {
+                    return Err( at_line( "the value is missing" ) );
+                }
+                word
+            },
+        };
+        let rest = chars.collect::<String>();
+        let rest = rest.trim();
+        if !rest.is_empty() && !rest.starts_with( '#' ) {
+            return Err( at_line( &format!( "unexpected {:?} after the value", rest ) ) );
+        }
+        entries.push( ( key.to_string(), Some( value ) ) );
+    }
+    Ok( entries )
+}
+
+// one object of options, the values are strings, numbers, booleans or null
+fn parse_json( text: &str ) -> ImageResult<Vec<( String, Option<String> )>> {
+    let mut entries = Vec::new();
+    let mut chars = text.chars().peekable();
+    skip_whitespace( &mut chars );
+    if chars.next() != Some( '{' ) {
+        return Err( syntax_error( "a JSON recipe is one object" ) );
+    }
+    skip_whitespace( &mut chars );
+    if chars.peek() == Some( &'}' ) {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace( &mut chars );
+            if chars.next() != Some( '"' ) {
+                return Err( syntax_error( "expected the name of an option in \"\"" ) );
+            }
+            let key = try!( read_quoted( &mut chars, '"', true ).map_err( | e | syntax_error( &e ) ) );
+            skip_whitespace( &mut chars );
+            if chars.next() != Some( ':' ) {
+                return Err( syntax_error( &format!( "expected : after {:?}", key ) ) );
+            }
+            skip_whitespace( &mut chars );
+            let value = match chars.peek().cloned() {
+                Some( '"' ) => { chars.next(); Some( try!( read_quoted( &mut chars, '"', true ).map_err( | e | syntax_error( &e ) ) ) ) },
+                Some( '{' ) | Some( '[' ) => return Err( recipe_error( &key, "must be a string, a number or a boolean" ) ),
+                _ => {
+                    let word = read_word( &mut chars, | c | c.is_whitespace() || c == ',' || c == '}' );
+                    match &word[..] {
+                        ""     => return Err( recipe_error( &key, "has no value" ) ),
+                        "null" => None,
+                        _      => Some( word ),
+                    }
+                },
+            };
+            entries.push( ( key, value ) );
+            skip_whitespace( &mut chars );
+            match chars.next() {
+                Some( ',' ) => continue,
+                Some( '}' ) => break,
+                _ => return Err( syntax_error( "expected , or } after a value" ) ),
+            }
+        }
+    }
+    skip_whitespace( &mut chars );
+    if chars.next().is_some() {
+        return Err( syntax_error( "unexpected text after the object" ) );
+    }
+    Ok( entries )
+}
+
+fn skip_whitespace<I: Iterator<Item=char>>( chars: &mut ::std::iter::Peekable<I> ) {
+    while chars.peek().map( | c | c.is_whitespace() ).unwrap_or( false ) {
+        chars.next();
+    }
+}
+
+// a number or a boolean, up to the first character that `ends` it
+fn read_word<I: Iterator<Item=char>, F: Fn( char ) -> bool>( chars: &mut ::std::iter::Peekable<I>, ends: F ) -> String {
+    let mut word = String::new();
+    while let Some( &c ) = chars.peek() {
+        if ends( c ) {
+            break;
+        }
+        word.push( c );
+        chars.next();
+    }
+    word
+}
+
+// the rest of a string after its opening quote, with the escapes of JSON when `escapes` is set
+fn read_quoted<I: Iterator<Item=char>>( chars: &mut I, quote: char, escapes: bool ) -> Result<String, String> {
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            None => return Err( "a string is not closed".to_string() ),
+            Some( c ) if c == quote => return Ok( value ),
+            Some( '\\' ) if escapes => match chars.next() {
+                Some( 'n' )  => value.push( '\n' ),
+                Some( 't' )  => value.push( '\t' ),
+                Some( 'r' )  => value.push( '\r' ),
+                Some( '"' )  => value.push( '"' ),
+                Some( '\\' ) => value.push( '\\' ),
+                Some( '/' )  => value.push( '/' ),
+                Some( 'u' )  => {
+                    let hex = chars.by_ref().take( 4 ).collect::<String>();
+                    match u32::from_str_radix( &hex, 16 ).ok().and_then( ::std::char::from_u32 ) {
+                        Some( c ) => value.push( c ),
+                        None => return Err( format!( "\\u{} is not a valid escape", hex ) ),
+                    }
+                },
+                Some( c ) => return Err( format!( "\\{} is not a valid escape", c ) ),
+                None => return Err( "a string is not closed".to_string() ),
+            },
+            Some( c ) => value.push( c ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_recipe;
+
+    fn entries( text: &str, is_json: bool ) -> Vec<( String, String )> {
+        parse_recipe( text, is_json ).unwrap().entries
+    }
+
+    fn pair( key: &str, value: &str ) -> ( String, String ) {
+        ( key.to_string(), value.to_string() )
+    }
+
+    #[test]
+    fn toml_values() {
+        let text = "# a recipe\n\nopen_threshold = 0.3\nignore_edges=10 # edges\nopen_method = \"row 0.5\"\nchannel = 'signal'\n";
+        assert_eq!( entries( text, false ), vec![ pair( "open_threshold", "0.3" ), pair( "ignore_edges", "10" ),
+                                                  pair( "open_method", "row 0.5" ), pair( "channel", "signal" ) ] );
+    }
+
+    #[test]
+    fn toml_quoting_and_escapes() {
+        // a # in a string is not a comment, and a literal string keeps its backslashes
+        let text = "pairing = \"open C1717 C2525; # not a comment\" # a comment\ninclude = 'lot1\\x*'\nexclude = \"a\\\"b\\\\c\\td\\u0041\"\n";
+        assert_eq!( entries( text, false ), vec![ pair( "pairing", "open C1717 C2525; # not a comment" ),
+                                                  pair( "include", "lot1\\x*" ), pair( "exclude", "a\"b\\c\tdA" ) ] );
+    }
+
+    #[test]
+    fn toml_errors() {
+        for text in [ "[table]\n", "open_threshold\n", "open_threshold =\n", "open_threshold = \"0.3\n",
+                      "open_threshold = 0.3 0.4\n", "dead_band = \"\\q\"\n", "a b = 1\n" ].iter() {
+            assert!( parse_recipe( text, false ).is_err() );
+        }
+    }
+
+    #[test]
+    fn json_values() {
+        let text = "{ \"open_threshold\": 0.3, \"ignore_edges\" : 10,\n  \"open_method\": \"row 0.5\", \"spec\": null, \"cache\": \"a\\\"b\\\\c\\/d\\u00e9\" }";
+        assert_eq!( entries( text, true ), vec![ pair( "open_threshold", "0.3" ), pair( "ignore_edges", "10" ),
+                                                 pair( "open_method", "row 0.5" ), pair( "cache", "a\"b\\c/d\u{e9}" ) ] );
+        assert_eq!( entries( " { } ", true ), vec![] );
+    }
+
+    #[test]
+    fn json_null_leaves_the_default() {
+        let recipe = parse_recipe( "{ \"spec\": null }", true ).unwrap();
+        assert!( recipe.entries.is_empty() );
+        assert_eq!( recipe.get( "spec" ), None );
+    }
+
+    #[test]
+    fn json_errors() {
+        for text in [ "[]", "{ \"jobs\": 1", "{ \"jobs\" 1 }", "{ \"jobs\": [1] }", "{ \"jobs\": }",
+                      "{ \"jobs\": 1 } x", "{ jobs: 1 }", "{ \"jobs\": \"\\x\" }" ].iter() {
+            assert!( parse_recipe( text, true ).is_err() );
+        }
+    }
+
+    #[test]
+    fn unknown_and_duplicate_keys() {
+        for &( text, is_json ) in [ ( "open_treshold = 0.3\n", false ), ( "{ \"open_treshold\": 0.3 }", true ),
+                                    ( "jobs = 1\njobs = 2\n", false ), ( "{ \"jobs\": 1, \"jobs\": 2 }", true ) ].iter() {
+            let e = parse_recipe( text, is_json ).unwrap_err().to_string();
+            assert!( e.contains( "is not an option of a recipe" ) || e.contains( "is given twice" ) );
+        }
+        let e = parse_recipe( "jobs = 1\njobs = 2\n", false ).unwrap_err().to_string();
+        assert!( e.contains( "\"jobs\" is given twice" ) );
+        let e = parse_recipe( "open_treshold = 0.3\n", false ).unwrap_err().to_string();
+        assert!( e.contains( "\"open_treshold\" is not an option of a recipe" ) );
+    }
+}
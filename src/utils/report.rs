@@ -0,0 +1,280 @@
+use std::io;
+use std::io::Write;
+use std::str::FromStr;
+
+use image::other::{
+    OpenTestResult,
+    ShortTestResult
+};
+
+use utils::binning::Bin;
+use utils::clusters::ClusterSummary;
+use utils::file::CaptureName;
+
+/// How the results are written
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub enum OutputFormat {
+    /// a header line and one comma separated line per die
+    Csv,
+    /// one array with an object per die
+    Json,
+    /// one object per die and per line
+    Jsonl,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str( s: &str ) -> Result<OutputFormat, String> {
+        match s.trim() {
+            "csv"   => Ok( OutputFormat::Csv ),
+            "json"  => Ok( OutputFormat::Json ),
+            "jsonl" => Ok( OutputFormat::Jsonl ),
+            other   => Err( format!( "{:?} is not an output format, use csv, json or jsonl", other ) ),
+        }
+    }
+}
+
+/// The field names of a die, in the order of the csv columns
+pub const COLUMNS: &'static [&'static str] = &[
+    "test_no", "lot", "wafer", "x", "y",
+    "open_bad_pixels", "open_threshold", "open_bads_in_bad_cols", "open_bads_in_bad_rows",
+    "open_bad_cols", "open_bad_rows", "open_bad_col_segments", "open_bad_row_segments",
+    "open_clusters", "open_largest_cluster", "open_cluster_sizes",
+    "short_bad_pixels", "short_threshold", "short_bads_not_in_bad_lines",
+    "bad_lines", "adjacent_bad_lines",
+    "short_clusters", "short_largest_cluster", "short_cluster_sizes",
+    "measured_pixels", "frames",
+    "die_no", "stage_x", "stage_y", "timestamp",
+    "bin",
+    "error",
+];
+
+/// The results of a die that could be analysed
+#[derive(Debug, Clone)]
+pub struct DieSummary {
+    /// the fewest frames of the captures of the die
+    pub frames         : usize,
+    pub open           : OpenTestResult,
+    pub short          : ShortTestResult,
+    pub open_clusters  : ClusterSummary,
+    pub short_clusters : ClusterSummary,
+}
+
+/// One die of the output
+#[derive(Debug, Clone)]
+pub struct DieRecord {
+    pub test_no : usize,
+    /// names of the lot and wafer directories the die was found in
+    pub lot     : String,
+    pub wafer   : String,
+    pub x       : u32,
+    pub y       : u32,
+    /// metadata of the earliest capture of the die, None when no name of its captures could be read
+    pub capture : Option<CaptureName>,
+    /// None when the die could not be analysed
+    pub summary : Option<DieSummary>,
+    /// None when no spec limits were given
+    pub bin     : Option<Bin>,
+    /// why the die could not be analysed, or its images could not be written
+    pub error   : Option<String>,
+}
+
+/// A field of the output
+#[derive(PartialEq, Debug, Clone)]
+pub enum Value {
+    Int( i64 ),
+    Float( f64 ),
+    Text( String ),
+    Null,
+}
+
+impl Value {
+    // goes through the shortest text of the f32, so that 0.3f32 is written as 0.3 and not 0.30000001192092896
+    pub fn from_f32( value: f32 ) -> Value {
+        Value::Float( format!( "{}", value ).parse::<f64>().unwrap_or( value as f64 ) )
+    }
+}
+
+impl DieRecord {
+    /// The fields of the die, in the order of `COLUMNS`
+    pub fn values( &self ) -> Vec<Value> {
+        let mut values = vec![ Value::Int( self.test_no as i64 ), Value::Text( self.lot.clone() ), Value::Text( self.wafer.clone() ),
+                               Value::Int( self.x as i64 ), Value::Int( self.y as i64 ) ];
+        match self.summary {
+            Some( DieSummary { frames, ref open, ref short, ref open_clusters, ref short_clusters } ) => {
+                values.push( Value::Int( open.bad_opens as i64 ) );
+                values.push( open.open_threshold.map( Value::from_f32 ).unwrap_or( Value::Null ) );
+                values.push( Value::Int( open.number_of_open_bads_in_bad_cols as i64 ) );
+                values.push( Value::Int( open.number_of_open_bads_in_bad_rows as i64 ) );
+                values.push( Value::Int( open.number_of_bad_columns as i64 ) );
+                values.push( Value::Int( open.number_of_bad_rows as i64 ) );
+                values.push( Value::Int( open.number_of_bad_col_segments as i64 ) );
+                values.push( Value::Int( open.number_of_bad_row_segments as i64 ) );
+                values.push( Value::Int( open_clusters.number_of_clusters as i64 ) );
+                values.push( Value::Int( open_clusters.largest_cluster as i64 ) );
+                values.push( Value::Text( open_clusters.histogram_text() ) );
+                values.push( Value::Int( short.bad_shorts as i64 ) );
+                values.push( Value::from_f32( short.threshold_for_shorts ) );
+                values.push( Value::Int( short.number_of_short_bads_not_in_bad_lines as i64 ) );
+                values.push( Value::Int( short.number_of_bad_lines as i64 ) );
+                values.push( Value::Int( short.number_of_adjacent_bad_lines as i64 ) );
+                values.push( Value::Int( short_clusters.number_of_clusters as i64 ) );
+                values.push( Value::Int( short_clusters.largest_cluster as i64 ) );
+                values.push( Value::Text( short_clusters.histogram_text() ) );
+                values.push( Value::Int( open.number_of_pixels_measured as i64 ) );
+                values.push( Value::Int( frames as i64 ) );
+            },
+            None => {
+                // keep the columns so that the failed die still lines up
+                while values.len() < COLUMNS.len() - 6 {
+                    values.push( Value::Null );
+                }
+            },
+        }
+        match self.capture {
+            Some( ref capture ) => {
+                values.push( Value::Int( capture.die as i64 ) );
+                values.push( Value::Int( capture.stage_x as i64 ) );
+                values.push( Value::Int( capture.stage_y as i64 ) );
+                values.push( Value::Text( capture.timestamp.clone() ) );
+            },
+            None => values.extend( vec![ Value::Null; 4 ] ),
+        }
+        values.push( match self.bin {
+            Some( bin ) => Value::Text( bin.name().to_string() ),
+            None => Value::Null,
+        } );
+        values.push( match self.error {
+            Some( ref error ) => Value::Text( error.clone() ),
+            None => Value::Null,
+        } );
+        values
+    }
+}
+
+/// Writes the dies in one of the output formats.
+/// Nothing but the results is written, so that the output can be read by other tools as it is.
+pub struct ReportWriter {
+    format       : OutputFormat,
+    out          : Box<dyn Write>,
+    dies_written : usize,
+}
+
+impl ReportWriter {
+    pub fn new( format: OutputFormat, out: Box<dyn Write> ) -> ReportWriter {
+        ReportWriter {
+            format       : format,
+            out          : out,
+            dies_written : 0,
+        }
+    }
+
+    /// Writes what comes before the first die: the csv header or the opening of the json array
+    pub fn begin( &mut self ) -> io::Result<()> {
+        match self.format {
+            OutputFormat::Csv   => writeln!( self.out, "{}", COLUMNS.join( "," ) ),
+            OutputFormat::Json  => write!( self.out, "[" ),
+            OutputFormat::Jsonl => Ok( () ),
+        }
+    }
+
+    pub fn write_die( &mut self, record: &DieRecord ) -> io::Result<()> {
+        let values = record.values();
+        match self.format {
+            OutputFormat::Csv => {
+                let fields = values.iter().map( csv_field ).collect::<Vec<String>>();
+                try!( writeln!( self.out, "{}", fields.join( "," ) ) );
+            },
+            OutputFormat::Json => {
+                let separator = if self.dies_written == 0 { "\n" } else { ",\n" };
+                try!( write!( self.out, "{}{}", separator, json_object( &values ) ) );
+            },
+            OutputFormat::Jsonl => {
+                try!( writeln!( self.out, "{}", json_object( &values ) ) );
+            },
+        }
+        self.dies_written += 1;
+        Ok( () )
+    }
+
+    /// Closes the json array and flushes the output
+    pub fn finish( &mut self ) -> io::Result<()> {
+        if self.format == OutputFormat::Json {
+            try!( writeln!( self.out, "\n]" ) );
+        }
+        self.out.flush()
+    }
+}
+
+fn csv_field( value: &Value ) -> String {
+    match *value {
+        Value::Int( v )   => v.to_string(),
+        Value::Float( v ) => v.to_string(),
+        Value::Text( ref text ) => {
+            if text.contains( ',' ) || text.contains( '"' ) || text.contains( '\n' ) || text.contains( '\r' ) || text.trim() != text {
+                format!( "\"{}\"", text.replace( "\"", "\"\"" ) )
+            } else {
+                text.clone()
+            }
+        },
+        Value::Null => String::new(),
+    }
+}
+
+fn json_object( values: &Vec<Value> ) -> String {
+    let members = COLUMNS.iter().zip( values.iter() )
+                         .map( | ( name, value ) | format!( "{}:{}", json_string( name ), json_value( value ) ) )
+                         .collect::<Vec<String>>();
+    format!( "{{{}}}", members.join( "," ) )
+}
+
+/// Writes the effective recipe as TOML that can be given back to --recipe, one option per line
+pub fn write_recipe<W: Write>( w: &mut W, recipe: &[( &str, Value )] ) -> io::Result<()> {
+    for &( name, ref value ) in recipe.iter() {
+        if let Some( value ) = toml_value( value ) {
+            try!( writeln!( w, "{} = {}", name, value ) );
+        }
+    }
+    w.flush()
+}
+
+// TOML has no null, an option that is not set is left out
+fn toml_value( value: &Value ) -> Option<String> {
+    match *value {
+        Value::Int( v ) => Some( v.to_string() ),
+        Value::Float( v ) if v.is_nan() => Some( "nan".to_string() ),
+        Value::Float( v ) => Some( v.to_string() ),
+        // the escapes of a TOML basic string are the ones of json
+        Value::Text( ref text ) => Some( json_string( text ) ),
+        Value::Null => None,
+    }
+}
+
+fn json_value( value: &Value ) -> String {
+    match *value {
+        Value::Int( v ) => v.to_string(),
+        // json has no NaN or infinity
+        Value::Float( v ) => if v.is_finite() { v.to_string() } else { "null".to_string() },
+        Value::Text( ref text ) => json_string( text ),
+        Value::Null => "null".to_string(),
+    }
+}
+
+fn json_string( text: &str ) -> String {
+    let mut quoted = String::with_capacity( text.len() + 2 );
+    quoted.push( '"' );
+    for c in text.chars() {
+        match c {
+            '"'  => quoted.push_str( "\\\"" ),
+            '\\' => quoted.push_str( "\\\\" ),
+            '\n' => quoted.push_str( "\\n" ),
+            '\r' => quoted.push_str( "\\r" ),
+            '\t' => quoted.push_str( "\\t" ),
+            c if ( c as u32 ) < 0x20 => quoted.push_str( &format!( "\\u{:04x}", c as u32 ) ),
+            c => quoted.push( c ),
+        }
+    }
+    quoted.push( '"' );
+    quoted
+}
@@ -0,0 +1,145 @@
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::error::ImageResult;
+use image::other::ShortDiagonalStats;
+
+use super::dimensions::Dimensions;
+use super::file::write_file;
+
+/// Direction of the lines along which the short bads are counted.
+/// The names follow the dead band: the diagonal keeps col - row and the anti diagonal row + col.
+#[derive(Copy, PartialEq, Eq, Debug, Clone)]
+pub enum LineOrientation {
+    Row,
+    Column,
+    /// top left to bottom right
+    Diagonal,
+    /// top right to bottom left, the lines of the short test before the orientation could be chosen
+    AntiDiagonal,
+}
+
+impl FromStr for LineOrientation {
+    type Err = String;
+
+    fn from_str( s: &str ) -> Result<LineOrientation, String> {
+        match s.trim() {
+            "row"           => Ok( LineOrientation::Row ),
+            "column"        => Ok( LineOrientation::Column ),
+            "diagonal"      => Ok( LineOrientation::Diagonal ),
+            "anti_diagonal" => Ok( LineOrientation::AntiDiagonal ),
+            other           => Err( format!( "{:?} is not a line orientation, use row, column, diagonal or anti_diagonal", other ) ),
+        }
+    }
+}
+
+impl LineOrientation {
+    pub fn name( &self ) -> &'static str {
+        match *self {
+            LineOrientation::Row          => "row",
+            LineOrientation::Column       => "column",
+            LineOrientation::Diagonal     => "diagonal",
+            LineOrientation::AntiDiagonal => "anti_diagonal",
+        }
+    }
+
+    /// Number of lines across an image, next lines have next numbers
+    pub fn number_of_lines( &self, dims: &Dimensions ) -> usize {
+        match *self {
+            LineOrientation::Row    => dims.height,
+            LineOrientation::Column => dims.width,
+            _                       => dims.width + dims.height - 1,
+        }
+    }
+
+    /// The line a pixel is on
+    pub fn line( &self, dims: &Dimensions, row: usize, col: usize ) -> usize {
+        match *self {
+            LineOrientation::Row          => row,
+            LineOrientation::Column       => col,
+            LineOrientation::Diagonal     => col + dims.height - 1 - row,
+            LineOrientation::AntiDiagonal => row + col,
+        }
+    }
+}
+
+/// Parses orientations separated by `,`, e.g. `row,anti_diagonal`.
+pub fn parse_orientations( spec: &str ) -> Result<Vec<LineOrientation>, String> {
+    let mut orientations = Vec::new();
+    for word in spec.split( ',' ).map( | w | w.trim() ).filter( | w | !w.is_empty() ) {
+        let orientation = try!( word.parse::<LineOrientation>() );
+        if orientations.contains( &orientation ) {
+            return Err( format!( "the line orientation {} is given twice", word ) );
+        }
+        orientations.push( orientation );
+    }
+    if orientations.is_empty() {
+        return Err( "at least one line orientation is needed for the short test".to_string() );
+    }
+    Ok( orientations )
+}
+
+/// The short bads of every line of one orientation
+pub struct ShortLines {
+    pub orientation : LineOrientation,
+    /// a line is bad when more than this fraction of its measured pixels are short bads
+    pub fraction    : f32,
+    /// in the order of the lines
+    pub lines       : Vec<ShortDiagonalStats>,
+}
+
+impl ShortLines {
+    pub fn new( orientation: LineOrientation, fraction: f32, dims: &Dimensions ) -> ShortLines {
+        ShortLines {
+            orientation : orientation,
+            fraction    : fraction,
+            lines       : vec![ ShortDiagonalStats { number_of_pixels_measured: 0, number_of_bad_shorts: 0 }; orientation.number_of_lines( dims ) ],
+        }
+    }
+
+    pub fn is_bad( &self, line: usize ) -> bool {
+        let stats = &self.lines[ line ];
+        stats.number_of_bad_shorts as f64 > self.fraction as f64 * stats.number_of_pixels_measured as f64
+    }
+
+    pub fn number_of_bad_lines( &self ) -> usize {
+        ( 0..self.lines.len() ).filter( | line | self.is_bad( *line ) ).count()
+    }
+
+    /// Lengths of the runs of bad lines next to each other, in the order of the lines
+    pub fn bad_runs( &self ) -> Vec<usize> {
+        let mut runs = Vec::new();
+        let mut run = 0;
+        for line in 0..self.lines.len() {
+            if self.is_bad( line ) {
+                run += 1;
+            } else if run > 0 {
+                runs.push( run );
+                run = 0;
+            }
+        }
+        if run > 0 {
+            runs.push( run );
+        }
+        runs
+    }
+
+    /// Pairs of bad lines next to each other, a run of n bad lines holds n - 1 of them
+    pub fn number_of_adjacent_bad_lines( &self ) -> usize {
+        self.bad_runs().iter().fold( 0, | sum, run | sum + run - 1 )
+    }
+}
+
+/// Writes the counts of every line of every orientation as csv, one line per row.
+pub fn write_short_lines( output_path: &Path, short_lines: &Vec<ShortLines> ) -> ImageResult<()> {
+    write_file( output_path, | w | {
+        try!( writeln!( w, "orientation,line,measured_pixels,short_bad_pixels,bad" ) );
+        for lines in short_lines.iter() {
+            for ( i, stats ) in lines.lines.iter().enumerate() {
+                try!( writeln!( w, "{},{},{},{},{}", lines.orientation.name(), i, stats.number_of_pixels_measured, stats.number_of_bad_shorts, lines.is_bad( i ) ) );
+            }
+        }
+        Ok( () )
+    } )
+}
@@ -0,0 +1,179 @@
+// Order statistics of pixel values. They select instead of sorting, so they take linear time
+// on the millions of pixels of a die, and leave out the NaN values.
+
+/// Returns the value that would be at `k` if `values` were sorted, reordering `values` on the way.
+/// `values` must not hold NaN and `k` must be less than its length.
+pub fn select_nth( values: &mut [f32], k: usize ) -> f32 {
+    let mut lo = 0;
+    let mut hi = values.len() - 1;
+    while lo < hi {
+        let pivot = median_of_three( values[lo], values[ lo + ( hi - lo ) / 2 ], values[hi] );
+        // three way partition, so that runs of equal values do not make it quadratic:
+        // [lo, lt) < pivot, [lt, gt] == pivot, ( gt, hi] > pivot
+        let ( mut lt, mut i, mut gt ) = ( lo, lo, hi );
+        while i <= gt {
+            if values[i] < pivot {
+                values.swap( lt, i );
+                lt += 1;
+                i += 1;
+            } else if values[i] > pivot {
+                values.swap( i, gt );
+                // the pivot is in [i, gt], so gt does not go below i
+                gt -= 1;
+            } else {
+                i += 1;
+            }
+        }
+        if k < lt {
+            hi = lt - 1;
+        } else if k > gt {
+            lo = gt + 1;
+        } else {
+            return pivot;
+        }
+    }
+    values[k]
+}
+
+fn median_of_three( a: f32, b: f32, c: f32 ) -> f32 {
+    if ( a <= b ) == ( b <= c ) {
+        b
+    } else if ( b <= a ) == ( a <= c ) {
+        a
+    } else {
+        c
+    }
+}
+
+/// The `p` th percentile, 0 to 100, of the values that are not NaN,
+/// interpolated between the two closest values. None if there are no such values.
+pub fn percentile( mut values: Vec<f32>, p: f64 ) -> Option<f32> {
+    values.retain( | v | !v.is_nan() );
+    if values.is_empty() {
+        return None;
+    }
+    let rank = p.max( 0.0 ).min( 100.0 ) / 100.0 * ( values.len() - 1 ) as f64;
+    let lower = rank.floor() as usize;
+    let fraction = rank - lower as f64;
+    let low = select_nth( &mut values, lower );
+    if fraction > 0.0 {
+        // after the selection, the next value in order is the smallest of the ones after `lower`
+        let high = values[ lower + 1.. ].iter().fold( ::std::f32::INFINITY, | min, v | min.min( *v ) );
+        Some( ( low as f64 + ( high - low ) as f64 * fraction ) as f32 )
+    } else {
+        Some( low )
+    }
+}
+
+/// The median of the values that are not NaN, the mean of the two middle values for an even number of them.
+pub fn median( values: Vec<f32> ) -> Option<f32> {
+    percentile( values, 50.0 )
+}
+
+/// The median and the median absolute deviation of the values that are not NaN.
+pub fn median_and_mad( values: Vec<f32> ) -> Option<( f32, f32 )> {
+    let values = values.into_iter().filter( | v | !v.is_nan() ).collect::<Vec<f32>>();
+    let centre = match median( values.clone() ) {
+        Some( centre ) => centre,
+        None => return None,
+    };
+    let deviations = values.into_iter().map( | v | ( v - centre ).abs() ).collect::<Vec<f32>>();
+    median( deviations ).map( | mad | ( centre, mad ) )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        median,
+        median_and_mad,
+        percentile,
+        select_nth
+    };
+
+    use std::f32::NAN;
+
+    // values in a scrambled order, with repeats, from a small linear congruential generator
+    fn scrambled( n: usize, distinct: u32 ) -> Vec<f32> {
+        let mut state = 12345u32;
+        ( 0..n ).map( | _ | {
+            state = state.wrapping_mul( 1103515245 ).wrapping_add( 12345 );
+            ( ( state >> 16 ) % distinct ) as f32
+        } ).collect()
+    }
+
+    fn sorted( values: &[f32] ) -> Vec<f32> {
+        let mut sorted = values.to_vec();
+        sorted.sort_by( | a, b | a.partial_cmp( b ).unwrap() );
+        sorted
+    }
+
+    #[test]
+    fn select_nth_matches_sorting_for_every_rank() {
+        for &( n, distinct ) in [ ( 1, 1 ), ( 2, 2 ), ( 7, 3 ), ( 8, 100 ), ( 101, 5 ), ( 256, 1000 ) ].iter() {
+            let values = scrambled( n, distinct );
+            let expected = sorted( &values );
+            for k in 0..n {
+                let mut work = values.clone();
+                assert_eq!( ( n, k, select_nth( &mut work, k ) ), ( n, k, expected[k] ) );
+            }
+        }
+    }
+
+    #[test]
+    fn select_nth_of_equal_values() {
+        let mut values = vec![ 4.0; 9 ];
+        for k in 0..9 {
+            assert_eq!( select_nth( &mut values, k ), 4.0 );
+        }
+    }
+
+    #[test]
+    fn median_of_odd_and_even_lengths() {
+        assert_eq!( median( vec![ 3.0, 1.0, 2.0 ] ), Some( 2.0 ) );
+        assert_eq!( median( vec![ 4.0, 1.0, 3.0, 2.0 ] ), Some( 2.5 ) );
+        assert_eq!( median( vec![ 5.0 ] ), Some( 5.0 ) );
+        assert_eq!( median( vec![ 5.0, 1.0 ] ), Some( 3.0 ) );
+    }
+
+    #[test]
+    fn median_of_equal_values() {
+        assert_eq!( median( vec![ 7.0; 6 ] ), Some( 7.0 ) );
+        assert_eq!( median( vec![ 7.0; 5 ] ), Some( 7.0 ) );
+    }
+
+    #[test]
+    fn median_leaves_out_nan() {
+        assert_eq!( median( vec![ NAN, 1.0, NAN, 3.0 ] ), Some( 2.0 ) );
+        assert_eq!( median( vec![ 2.0, NAN, 9.0, 4.0 ] ), Some( 4.0 ) );
+        assert_eq!( median( vec![ NAN, NAN ] ), None );
+    }
+
+    #[test]
+    fn empty_input_has_no_statistics() {
+        assert_eq!( median( Vec::new() ), None );
+        assert_eq!( percentile( Vec::new(), 50.0 ), None );
+        assert_eq!( median_and_mad( Vec::new() ), None );
+        assert_eq!( median_and_mad( vec![ NAN ] ), None );
+    }
+
+    #[test]
+    fn percentile_at_the_ends_and_between() {
+        let values = vec![ 9.0, 1.0, 5.0, NAN, 3.0, 7.0 ];
+        assert_eq!( percentile( values.clone(), 0.0 ), Some( 1.0 ) );
+        assert_eq!( percentile( values.clone(), 100.0 ), Some( 9.0 ) );
+        assert_eq!( percentile( values.clone(), 25.0 ), Some( 3.0 ) );
+        assert_eq!( percentile( values.clone(), 12.5 ), Some( 2.0 ) );
+        // out of range is clamped
+        assert_eq!( percentile( values.clone(), -10.0 ), Some( 1.0 ) );
+        assert_eq!( percentile( values, 150.0 ), Some( 9.0 ) );
+    }
+
+    #[test]
+    fn median_and_mad_of_odd_and_even_lengths() {
+        // deviations 2 1 0 1 97
+        assert_eq!( median_and_mad( vec![ 1.0, 2.0, 3.0, 4.0, 100.0 ] ), Some( ( 3.0, 1.0 ) ) );
+        // median 2.5, deviations 1.5 0.5 0.5 1.5
+        assert_eq!( median_and_mad( vec![ 4.0, 1.0, 3.0, 2.0 ] ), Some( ( 2.5, 1.0 ) ) );
+        assert_eq!( median_and_mad( vec![ 6.0, NAN, 6.0, 6.0 ] ), Some( ( 6.0, 0.0 ) ) );
+    }
+}
@@ -0,0 +1,325 @@
+use image::other::BadType;
+
+use super::dimensions::Dimensions;
+use super::stats::{
+    median,
+    median_and_mad,
+    percentile
+};
+
+/// How the open test decides that a pixel of the open difference is an open bad.
+/// The local references only take the measured pixels, so the dead band and the ignored edges do not pull them down.
+#[derive(Copy, PartialEq, Debug, Clone)]
+pub enum OpenThreshold {
+    /// below this value
+    Global( f32 ),
+    /// below this fraction of the median, over the rows at most `radius` rows away, of the median of the measured pixels
+    /// of that row at most `radius` columns away. This median of medians is a separable approximation of the median of the
+    /// square around the pixel: it is close to it on smooth images but not the same, and much cheaper for a large radius.
+    SeparableMedian { radius: usize, fraction: f32 },
+    /// below this fraction of the median of the measured pixels of its row
+    Row( f32 ),
+    /// below this fraction of the median of the measured pixels of its column
+    Column( f32 ),
+}
+
+impl OpenThreshold {
+    /// Parses `global`, `separable_median <radius> <fraction>`, `row <fraction>` or `column <fraction>`.
+    /// `global` takes `threshold`, the value of the -o option.
+    pub fn parse( spec: &str, threshold: f32 ) -> Result<OpenThreshold, String> {
+        let words: Vec<&str> = spec.split_whitespace().collect();
+        let fraction = | word: &str | match word.parse::<f32>() {
+            Ok( v ) if v.is_finite() => Ok( v ),
+            _ => Err( format!( "invalid open threshold {:?}: {:?} is not a valid number", spec, word ) ),
+        };
+        match ( words.get( 0 ).cloned().unwrap_or( "" ), words.len() ) {
+            ( "global", 1 )           => Ok( OpenThreshold::Global( threshold ) ),
+            ( "separable_median", 3 ) => {
+                let radius = match words[1].parse::<usize>() {
+                    Ok( r ) if r > 0 => r,
+                    _ => return Err( format!( "invalid open threshold {:?}: the radius must be a whole number of pixels above 0", spec ) ),
+                };
+                Ok( OpenThreshold::SeparableMedian { radius: radius, fraction: try!( fraction( words[2] ) ) } )
+            },
+            ( "row", 2 )              => Ok( OpenThreshold::Row( try!( fraction( words[1] ) ) ) ),
+            ( "column", 2 )           => Ok( OpenThreshold::Column( try!( fraction( words[1] ) ) ) ),
+            _ => Err( format!( "invalid open threshold {:?}: expected global, separable_median <radius> <fraction>, row <fraction> or column <fraction>", spec ) ),
+        }
+    }
+
+    /// The value reported as the open threshold: the threshold of `Global`, None for the local methods as every pixel has its own
+    pub fn value( &self ) -> Option<f32> {
+        match *self {
+            OpenThreshold::Global( threshold ) => Some( threshold ),
+            _ => None,
+        }
+    }
+
+    /// The threshold of every pixel. A pixel with no measured pixel around it gets NaN, so it is never an open bad.
+    pub fn pixel_thresholds( &self, dims: &Dimensions, values: &Vec<f32>, classes: &Vec<BadType> ) -> PixelThresholds {
+        let measured = | i: usize | classes[i] == BadType::Unknown && !values[i].is_nan();
+        PixelThresholds::Each( match *self {
+            OpenThreshold::Global( threshold ) => return PixelThresholds::All( threshold ),
+            OpenThreshold::Row( fraction ) => {
+                let mut thresholds = Vec::with_capacity( values.len() );
+                for row in 0..dims.height {
+                    let line = ( row * dims.width .. ( row + 1 ) * dims.width ).filter( | i | measured( *i ) ).map( | i | values[i] ).collect();
+                    let threshold = median( line ).map( | m | fraction * m ).unwrap_or( ::std::f32::NAN );
+                    for _ in 0..dims.width {
+                        thresholds.push( threshold );
+                    }
+                }
+                thresholds
+            },
+            OpenThreshold::Column( fraction ) => {
+                let mut thresholds = vec![ ::std::f32::NAN; values.len() ];
+                for col in 0..dims.width {
+                    let line = ( 0..dims.height ).map( | row | row * dims.width + col ).filter( | i | measured( *i ) ).map( | i | values[i] ).collect();
+                    let threshold = median( line ).map( | m | fraction * m ).unwrap_or( ::std::f32::NAN );
+                    for row in 0..dims.height {
+                        thresholds[ row * dims.width + col ] = threshold;
+                    }
+                }
+                thresholds
+            },
+            OpenThreshold::SeparableMedian { radius, fraction } => {
+                // the medians along the rows, then the medians of those along the columns
+                let mut row_medians = Vec::with_capacity( values.len() );
+                for row in 0..dims.height {
+                    let line = ( row * dims.width .. ( row + 1 ) * dims.width ).map( | i | if measured( i ) { values[i] } else { ::std::f32::NAN } ).collect::<Vec<f32>>();
+                    row_medians.extend( sliding_medians( &line, radius ) );
+                }
+                let mut thresholds = vec![ ::std::f32::NAN; values.len() ];
+                for col in 0..dims.width {
+                    let line = ( 0..dims.height ).map( | row | row_medians[ row * dims.width + col ] ).collect::<Vec<f32>>();
+                    for ( row, m ) in sliding_medians( &line, radius ).into_iter().enumerate() {
+                        thresholds[ row * dims.width + col ] = fraction * m;
+                    }
+                }
+                thresholds
+            },
+        } )
+    }
+}
+
+/// The thresholds of the pixels of a die, one for all of them or one for each
+#[derive(PartialEq, Debug, Clone)]
+pub enum PixelThresholds {
+    All( f32 ),
+    Each( Vec<f32> ),
+}
+
+impl PixelThresholds {
+    /// The threshold of the pixel at index `i`
+    pub fn at( &self, i: usize ) -> f32 {
+        match *self {
+            PixelThresholds::All( threshold )       => threshold,
+            PixelThresholds::Each( ref thresholds ) => thresholds[i],
+        }
+    }
+}
+
+// the median of the values that are not NaN at most `radius` places away, for every place of `line`, NaN where there is none.
+// The window is kept sorted, so a step only inserts and removes one value.
+fn sliding_medians( line: &[f32], radius: usize ) -> Vec<f32> {
+    let mut window: Vec<f32> = Vec::with_capacity( 2 * radius + 1 );
+    let mut medians = Vec::with_capacity( line.len() );
+    let find = | window: &Vec<f32>, v: f32 | window.binary_search_by( | w | w.partial_cmp( &v ).unwrap_or( ::std::cmp::Ordering::Equal ) );
+    for v in line.iter().take( radius ) {
+        if !v.is_nan() {
+            let at = find( &window, *v ).unwrap_or_else( | at | at );
+            window.insert( at, *v );
+        }
+    }
+    for i in 0..line.len() {
+        if let Some( v ) = line.get( i + radius ) {
+            if !v.is_nan() {
+                let at = find( &window, *v ).unwrap_or_else( | at | at );
+                window.insert( at, *v );
+            }
+        }
+        if i > radius && !line[ i - radius - 1 ].is_nan() {
+            if let Ok( at ) = find( &window, line[ i - radius - 1 ] ) {
+                window.remove( at );
+            }
+        }
+        let n = window.len();
+        medians.push( if n == 0 { ::std::f32::NAN } else if n % 2 == 1 { window[ n / 2 ] } else { ( window[ n / 2 - 1 ] + window[ n / 2 ] ) / 2.0 } );
+    }
+    medians
+}
+
+/// How the threshold of the short test is taken from the short difference of the measured pixels.
+/// Pixels below the threshold are short bads.
+#[derive(Copy, PartialEq, Debug, Clone)]
+pub enum ShortThreshold {
+    /// this fraction of the median
+    MedianFraction( f32 ),
+    /// this percentile, 0 to 100
+    Percentile( f32 ),
+    /// the median minus this many median absolute deviations
+    MedianMinusMad( f32 ),
+    /// this value, whatever the pixels
+    Absolute( f32 ),
+}
+
+impl ShortThreshold {
+    /// Parses `median [<fraction>]`, `percentile <p>`, `mad <k>` or `absolute <value>`.
+    /// `median` alone takes `fraction`, the value of the -s option.
+    pub fn parse( spec: &str, fraction: f32 ) -> Result<ShortThreshold, String> {
+        let words: Vec<&str> = spec.split_whitespace().collect();
+        let value = | word: &str | match word.parse::<f32>() {
+            Ok( v ) if v.is_finite() => Ok( v ),
+            _ => Err( format!( "invalid short threshold {:?}: {:?} is not a valid number", spec, word ) ),
+        };
+        match ( words.get( 0 ).cloned().unwrap_or( "" ), words.len() ) {
+            ( "median", 1 )     => Ok( ShortThreshold::MedianFraction( fraction ) ),
+            ( "median", 2 )     => Ok( ShortThreshold::MedianFraction( try!( value( words[1] ) ) ) ),
+            ( "percentile", 2 ) => {
+                let p = try!( value( words[1] ) );
+                if p < 0.0 || p > 100.0 {
+                    return Err( format!( "invalid short threshold {:?}: the percentile must be between 0 and 100", spec ) );
+                }
+                Ok( ShortThreshold::Percentile( p ) )
+            },
+            ( "mad", 2 )        => Ok( ShortThreshold::MedianMinusMad( try!( value( words[1] ) ) ) ),
+            ( "absolute", 2 )   => Ok( ShortThreshold::Absolute( try!( value( words[1] ) ) ) ),
+            _ => Err( format!( "invalid short threshold {:?}: expected median [<fraction>], percentile <p>, mad <k> or absolute <value>", spec ) ),
+        }
+    }
+
+    /// The threshold for these values, None if none of them is a number.
+    pub fn threshold( &self, values: Vec<f32> ) -> Option<f32> {
+        match *self {
+            ShortThreshold::MedianFraction( fraction ) => median( values ).map( | m | fraction * m ),
+            ShortThreshold::Percentile( p )            => percentile( values, p as f64 ),
+            ShortThreshold::MedianMinusMad( k )        => median_and_mad( values ).map( | ( m, mad ) | m - k * mad ),
+            ShortThreshold::Absolute( threshold )      => Some( threshold ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f32::NAN;
+
+    // equal, with NaN equal to NaN
+    fn same( a: &[f32], b: &[f32] ) -> bool {
+        a.len() == b.len() && a.iter().zip( b.iter() ).all( | ( x, y ) | x == y || ( x.is_nan() && y.is_nan() ) )
+    }
+
+    fn each( thresholds: PixelThresholds ) -> Vec<f32> {
+        match thresholds {
+            PixelThresholds::Each( thresholds ) => thresholds,
+            other => panic!( "expected a threshold per pixel, found {:?}", other ),
+        }
+    }
+
+    #[test]
+    fn sliding_medians_at_the_edges() {
+        // the window is cut at both ends, so the first and last medians are of two values
+        assert!( same( &sliding_medians( &[ 1.0, 2.0, 3.0, 4.0, 5.0 ], 1 ), &[ 1.5, 2.0, 3.0, 4.0, 4.5 ] ) );
+        assert!( same( &sliding_medians( &[ 1.0, 2.0, 3.0 ], 5 ), &[ 2.0, 2.0, 2.0 ] ) );
+        assert!( same( &sliding_medians( &[], 2 ), &[] ) );
+    }
+
+    #[test]
+    fn sliding_medians_skip_nan() {
+        assert!( same( &sliding_medians( &[ NAN, 1.0, NAN, NAN, NAN, 5.0 ], 1 ), &[ 1.0, 1.0, 1.0, NAN, 5.0, 5.0 ] ) );
+        assert!( same( &sliding_medians( &[ NAN, NAN ], 1 ), &[ NAN, NAN ] ) );
+    }
+
+    #[test]
+    fn sliding_medians_remove_one_of_equal_values() {
+        assert!( same( &sliding_medians( &[ 2.0, 2.0, 2.0, 7.0, 7.0, 7.0 ], 1 ), &[ 2.0, 2.0, 2.0, 7.0, 7.0, 7.0 ] ) );
+        assert!( same( &sliding_medians( &[ 3.0, 3.0, 1.0, 3.0, 9.0, 9.0, 3.0 ], 1 ), &[ 3.0, 3.0, 3.0, 3.0, 9.0, 9.0, 6.0 ] ) );
+    }
+
+    #[test]
+    fn sliding_medians_match_the_median_of_each_window() {
+        // values with repeats and gaps, from a small linear congruential generator
+        let mut state = 12345u32;
+        let line = ( 0..200 ).map( | _ | {
+            state = state.wrapping_mul( 1103515245 ).wrapping_add( 12345 );
+            let v = ( state >> 16 ) % 11;
+            if v == 0 { NAN } else { v as f32 }
+        } ).collect::<Vec<f32>>();
+        for radius in 1..6 {
+            let expected = ( 0..line.len() ).map( | i | {
+                let window = line[ i.saturating_sub( radius ) .. ( i + radius + 1 ).min( line.len() ) ].to_vec();
+                median( window ).unwrap_or( NAN )
+            } ).collect::<Vec<f32>>();
+            assert!( same( &sliding_medians( &line, radius ), &expected ), "radius {}", radius );
+        }
+    }
+
+    // 1 2 3
+    // 4 5 6
+    // 7 8 9
+    fn three_by_three() -> ( Dimensions, Vec<f32> ) {
+        ( Dimensions::new( 3, 3 ), ( 1..10 ).map( | v | v as f32 ).collect() )
+    }
+
+    #[test]
+    fn global_is_one_threshold() {
+        let ( dims, values ) = three_by_three();
+        let thresholds = OpenThreshold::Global( 4.5 ).pixel_thresholds( &dims, &values, &vec![ BadType::Unknown; 9 ] );
+        assert_eq!( thresholds, PixelThresholds::All( 4.5 ) );
+        assert_eq!( thresholds.at( 8 ), 4.5 );
+    }
+
+    #[test]
+    fn row_takes_the_measured_pixels_of_the_row() {
+        let ( dims, values ) = three_by_three();
+        let mut classes = vec![ BadType::Unknown; 9 ];
+        classes[3] = BadType::DeadBand;
+        let thresholds = each( OpenThreshold::Row( 0.5 ).pixel_thresholds( &dims, &values, &classes ) );
+        assert!( same( &thresholds, &[ 1.0, 1.0, 1.0, 2.75, 2.75, 2.75, 4.0, 4.0, 4.0 ] ) );
+    }
+
+    #[test]
+    fn column_takes_the_measured_pixels_of_the_column() {
+        let ( dims, values ) = three_by_three();
+        let mut classes = vec![ BadType::Unknown; 9 ];
+        classes[3] = BadType::DeadBand;
+        let thresholds = each( OpenThreshold::Column( 0.5 ).pixel_thresholds( &dims, &values, &classes ) );
+        assert!( same( &thresholds, &[ 2.0, 2.5, 3.0, 2.0, 2.5, 3.0, 2.0, 2.5, 3.0 ] ) );
+    }
+
+    #[test]
+    fn separable_median_is_the_median_of_the_row_medians() {
+        let ( dims, values ) = three_by_three();
+        let separable = OpenThreshold::SeparableMedian { radius: 1, fraction: 1.0 };
+        let thresholds = each( separable.pixel_thresholds( &dims, &values, &vec![ BadType::Unknown; 9 ] ) );
+        assert!( same( &thresholds, &[ 3.0, 3.5, 4.0, 4.5, 5.0, 5.5, 6.0, 6.5, 7.0 ] ) );
+        // a pixel with no measured pixel within the radius is never an open bad
+        let dims = Dimensions::new( 5, 1 );
+        let classes = vec![ BadType::DeadBand, BadType::DeadBand, BadType::DeadBand, BadType::Unknown, BadType::Unknown ];
+        let thresholds = each( separable.pixel_thresholds( &dims, &vec![ 1.0, 2.0, 3.0, 4.0, 6.0 ], &classes ) );
+        assert!( same( &thresholds, &[ NAN, NAN, 4.0, 5.0, 5.0 ] ) );
+    }
+
+    #[test]
+    fn open_threshold_keywords() {
+        assert_eq!( OpenThreshold::parse( "global", 0.3 ), Ok( OpenThreshold::Global( 0.3 ) ) );
+        assert_eq!( OpenThreshold::parse( " separable_median 4 0.5 ", 0.3 ), Ok( OpenThreshold::SeparableMedian { radius: 4, fraction: 0.5 } ) );
+        assert_eq!( OpenThreshold::parse( "row 0.5", 0.3 ), Ok( OpenThreshold::Row( 0.5 ) ) );
+        assert_eq!( OpenThreshold::parse( "column 0.5", 0.3 ), Ok( OpenThreshold::Column( 0.5 ) ) );
+        assert!( OpenThreshold::parse( "separable_median 0 0.5", 0.3 ).is_err() );
+        assert!( OpenThreshold::parse( "neighbourhood 4 0.5", 0.3 ).is_err() );
+    }
+
+    #[test]
+    fn short_thresholds() {
+        // median 3, median absolute deviation 1
+        let values = vec![ 1.0, 2.0, 3.0, 4.0, 100.0 ];
+        assert_eq!( ShortThreshold::MedianFraction( 0.5 ).threshold( values.clone() ), Some( 1.5 ) );
+        assert_eq!( ShortThreshold::Percentile( 25.0 ).threshold( values.clone() ), Some( 2.0 ) );
+        assert_eq!( ShortThreshold::MedianMinusMad( 2.0 ).threshold( values.clone() ), Some( 1.0 ) );
+        assert_eq!( ShortThreshold::Absolute( 7.0 ).threshold( values.clone() ), Some( 7.0 ) );
+        assert_eq!( ShortThreshold::MedianFraction( 0.5 ).threshold( vec![ NAN ] ), None );
+        assert_eq!( ShortThreshold::Absolute( 7.0 ).threshold( vec![] ), Some( 7.0 ) );
+    }
+}
@@ -0,0 +1,191 @@
+use std::cmp;
+use std::io::Write;
+use std::path::Path;
+
+use image::error::ImageResult;
+
+use super::file::write_file;
+use super::report::{
+    DieRecord,
+    Value,
+    COLUMNS
+};
+
+// the text fields can not be shown on a map
+const TEXT_COLUMNS: &'static [&'static str] = &[ "lot", "wafer", "open_cluster_sizes", "short_cluster_sizes", "timestamp", "bin", "error" ];
+
+// size in pixels of a die in the wafer map image, the last row and column are the grid
+const CELL_SIZE: usize = 16;
+
+/// One die position of a wafer map
+#[derive(Copy, PartialEq, Debug, Clone)]
+pub enum WaferCell {
+    /// the value of the metric for the die
+    Value( f64 ),
+    /// the die was found but could not be analysed
+    Failed,
+    /// there is no die directory at this position
+    Missing,
+}
+
+/// A metric of every die, placed by die x and y.
+/// x grows from left to right and y from top to bottom.
+#[derive(Debug, Clone)]
+pub struct WaferMap {
+    pub metric : String,
+    pub x_min  : u32,
+    pub y_min  : u32,
+    pub width  : usize,
+    pub height : usize,
+    /// row by row, `width * height` cells
+    pub cells  : Vec<WaferCell>,
+}
+
+/// Checks that `metric` is a numeric field of the output.
+pub fn check_metric( metric: &str ) -> Result<(), String> {
+    if COLUMNS.contains( &metric ) && !TEXT_COLUMNS.contains( &metric ) {
+        Ok( () )
+    } else {
+        let numeric = COLUMNS.iter().filter( | c | !TEXT_COLUMNS.contains( c ) ).cloned().collect::<Vec<&str>>();
+        Err( format!( "{:?} can not be shown on a wafer map, use one of {}", metric, numeric.join( ", " ) ) )
+    }
+}
+
+impl WaferMap {
+    /// Places the value of `metric` of every die on the grid that spans all the dies.
+    pub fn new( metric: &str, records: &Vec<DieRecord> ) -> WaferMap {
+        let index = COLUMNS.iter().position( | c | *c == metric ).unwrap_or( 0 );
+        let x_min = records.iter().map( | r | r.x ).min().unwrap_or( 0 );
+        let x_max = records.iter().map( | r | r.x ).max().unwrap_or( 0 );
+        let y_min = records.iter().map( | r | r.y ).min().unwrap_or( 0 );
+        let y_max = records.iter().map( | r | r.y ).max().unwrap_or( 0 );
+        let ( width, height ) = if records.is_empty() {
+            ( 0, 0 )
+        } else {
+            ( ( x_max - x_min + 1 ) as usize, ( y_max - y_min + 1 ) as usize )
+        };
+        let mut cells = vec![ WaferCell::Missing; width * height ];
+        for record in records.iter() {
+            let cell = match ( &record.summary, &record.values()[ index ] ) {
+                ( &Some( _ ), &Value::Int( v ) )   => WaferCell::Value( v as f64 ),
+                ( &Some( _ ), &Value::Float( v ) ) => WaferCell::Value( v ),
+                ( &Some( _ ), &Value::Null )       => WaferCell::Missing,
+                _ => WaferCell::Failed,
+            };
+            cells[ ( record.y - y_min ) as usize * width + ( record.x - x_min ) as usize ] = cell;
+        }
+        WaferMap {
+            metric : metric.to_string(),
+            x_min  : x_min,
+            y_min  : y_min,
+            width  : width,
+            height : height,
+            cells  : cells,
+        }
+    }
+
+    fn cell( &self, col: usize, row: usize ) -> WaferCell {
+        self.cells[ row * self.width + col ]
+    }
+
+    // smallest and largest value of the dies that were analysed
+    fn value_range( &self ) -> Option<( f64, f64 )> {
+        self.cells.iter().fold( None, | range, cell | match ( range, *cell ) {
+            ( None, WaferCell::Value( v ) ) => Some( ( v, v ) ),
+            ( Some( ( lo, hi ) ), WaferCell::Value( v ) ) => Some( ( lo.min( v ), hi.max( v ) ) ),
+            ( range, _ ) => range,
+        } )
+    }
+
+    /// Writes the map as an aligned text table, `.` for a missing die and `FAIL` for a failed one.
+    pub fn write_text<W: Write>( &self, w: &mut W ) -> ImageResult<()> {
+        let texts = self.cells.iter().map( | cell | match *cell {
+            WaferCell::Value( v ) => v.to_string(),
+            WaferCell::Failed     => "FAIL".to_string(),
+            WaferCell::Missing    => ".".to_string(),
+        } ).collect::<Vec<String>>();
+        let col_width = texts.iter().map( | t | t.len() ).chain( Some( format!( "x{}", self.x_min as usize + self.width.saturating_sub( 1 ) ).len() ) ).max().unwrap_or( 1 );
+        let label_width = cmp::max( self.metric.len(), format!( "y{}", self.y_min as usize + self.height.saturating_sub( 1 ) ).len() );
+
+        try!( write!( w, "{:<1$}", self.metric, label_width ) );
+        for col in 0..self.width {
+            try!( write!( w, " {:>1$}", format!( "x{}", self.x_min as usize + col ), col_width ) );
+        }
+        try!( writeln!( w, "" ) );
+        for row in 0..self.height {
+            try!( write!( w, "{:<1$}", format!( "y{}", self.y_min as usize + row ), label_width ) );
+            for col in 0..self.width {
+                try!( write!( w, " {:>1$}", texts[ row * self.width + col ], col_width ) );
+            }
+            try!( writeln!( w, "" ) );
+        }
+        Ok( () )
+    }
+
+    /// Writes the map as a csv matrix with the x values in the header and the y values in the first column.
+    /// A missing die is left empty and a failed one is `failed`.
+    pub fn write_csv<W: Write>( &self, w: &mut W ) -> ImageResult<()> {
+        try!( write!( w, "y\\x" ) );
+        for col in 0..self.width {
+            try!( write!( w, ",{}", self.x_min as usize + col ) );
+        }
+        try!( writeln!( w, "" ) );
+        for row in 0..self.height {
+            try!( write!( w, "{}", self.y_min as usize + row ) );
+            for col in 0..self.width {
+                match self.cell( col, row ) {
+                    WaferCell::Value( v ) => try!( write!( w, ",{}", v ) ),
+                    WaferCell::Failed     => try!( write!( w, ",failed" ) ),
+                    WaferCell::Missing    => try!( write!( w, "," ) ),
+                }
+            }
+            try!( writeln!( w, "" ) );
+        }
+        Ok( () )
+    }
+
+    /// Writes the map as a binary PPM image, a square per die on a white grid.
+    /// The values go from green for the smallest to red for the largest,
+    /// a failed die is black and a missing one light grey.
+    pub fn write_ppm<W: Write>( &self, w: &mut W ) -> ImageResult<()> {
+        let range = self.value_range();
+        let width = self.width * CELL_SIZE;
+        let height = self.height * CELL_SIZE;
+        try!( write!( w, "P6\n{} {}\n255\n", width, height ) );
+        for y in 0..height {
+            for x in 0..width {
+                let colour = if x % CELL_SIZE == CELL_SIZE - 1 || y % CELL_SIZE == CELL_SIZE - 1 {
+                    [ 255, 255, 255 ]
+                } else {
+                    match self.cell( x / CELL_SIZE, y / CELL_SIZE ) {
+                        WaferCell::Value( v ) => value_colour( v, range ),
+                        WaferCell::Failed     => [   0,   0,   0 ],
+                        WaferCell::Missing    => [ 192, 192, 192 ],
+                    }
+                };
+                try!( w.write_all( &colour ) );
+            }
+        }
+        Ok( () )
+    }
+}
+
+fn value_colour( value: f64, range: Option<( f64, f64 )> ) -> [u8; 3] {
+    let t = match range {
+        Some( ( lo, hi ) ) if hi > lo => ( value - lo ) / ( hi - lo ),
+        _ => 0.0,
+    };
+    [ ( 255.0 * t ).round() as u8, ( 255.0 * ( 1.0 - t ) ).round() as u8, 0 ]
+}
+
+/// Writes the wafer map of `metric` into `output_dir`, as `wafer_<metric>.txt`, `wafer_<metric>.csv` and `wafer_<metric>.ppm`.
+pub fn write_wafer_maps( output_dir: &Path, metric: &str, records: &Vec<DieRecord> ) -> ImageResult<()> {
+    let map = WaferMap::new( metric, records );
+    let txt = output_dir.join( format!( "wafer_{}.txt", metric ) );
+    try!( write_file( &txt, | w | map.write_text( w ) ) );
+    let csv = output_dir.join( format!( "wafer_{}.csv", metric ) );
+    try!( write_file( &csv, | w | map.write_csv( w ) ) );
+    let ppm = output_dir.join( format!( "wafer_{}.ppm", metric ) );
+    try!( write_file( &ppm, | w | map.write_ppm( w ) ) );
+    Ok( () )
+}